@@ -0,0 +1,103 @@
+use crate::*;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AuditAction {
+    MedicalHistoryRead,
+    MedicalHistoryUpdated,
+    ConsentGranted,
+    ConsentRevoked,
+    PrescriptionCreated,
+    DoctorApproved,
+    RoleGranted,
+    RoleRevoked,
+    FeeUpdated,
+    OwnerChanged,
+    MedicalRecordAmended,
+    EncryptedRecordsShared,
+    CancellationPolicyUpdated,
+    PharmacyApproved,
+    MedicineRecalled,
+    MedicineScheduleUpdated,
+}
+
+// One append-only entry of the audit trail. Entries are never updated or
+// removed; `id` is the position in `Contract::audit_log`.
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct AuditEntry {
+    pub(crate) id: u32,
+    pub(crate) actor: AccountId,
+    // Patient whose record was touched; `None` for platform-wide actions
+    pub(crate) patient_id: Option<u32>,
+    pub(crate) action: AuditAction,
+    pub(crate) timestamp: u64,
+}
+
+impl Contract {
+    // Records `action` by the caller and indexes it by patient and by actor
+    pub(crate) fn record_audit(&mut self, patient_id: Option<u32>, action: AuditAction) {
+        let actor = env::predecessor_account_id();
+        let id = self.audit_log.len();
+        self.audit_log.push(AuditEntry {
+            id,
+            actor: actor.clone(),
+            patient_id,
+            action,
+            timestamp: env::block_timestamp(),
+        });
+        if let Some(patient_id) = patient_id {
+            let ids = self
+                .audit_by_patient
+                .entry(patient_id)
+                .or_insert_with(|| Vector::new(StorageKey::AuditPatientEntries { patient_id }));
+            ids.push(id);
+            ids.flush();
+        }
+        let account_hash = env::sha256(actor.as_bytes());
+        let ids = self
+            .audit_by_actor
+            .entry(actor)
+            .or_insert_with(|| Vector::new(StorageKey::AuditActorEntries { account_hash }));
+        ids.push(id);
+        ids.flush();
+    }
+
+    // Audits a read. Other readers pay for the entry it leaves behind; the
+    // contract covers a patient's reads of their own record and readers
+    // without a storage account.
+    pub(crate) fn record_read_audit(&mut self, patient_id: u32) {
+        let reader = env::predecessor_account_id();
+        let billed = self.storage_accounts.contains_key(&reader)
+            && self.patients.get(&patient_id).is_some_and(|patient| patient.account_id != reader);
+        let initial_storage = env::storage_usage();
+        self.record_audit(Some(patient_id), AuditAction::MedicalHistoryRead);
+        if billed {
+            self.charge_storage(&reader, initial_storage);
+        }
+    }
+
+    fn audit_entries(&self, ids: Option<&Vector<u32>>, from_index: Option<u32>, limit: Option<u32>) -> Vec<AuditEntry> {
+        let Some(ids) = ids else {
+            return vec![];
+        };
+        let ids: Vec<u32> = paginate(ids.iter(), from_index, limit);
+        ids.iter().filter_map(|id| self.audit_log.get(*id)).cloned().collect()
+    }
+}
+
+#[near]
+impl Contract {
+    // Every access to a patient's record, oldest first
+    pub fn get_patient_audit_log(&self, patient_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<AuditEntry> {
+        self.audit_entries(self.audit_by_patient.get(&patient_id), from_index, limit)
+    }
+
+    pub fn get_actor_audit_log(&self, actor: AccountId, from_index: Option<u32>, limit: Option<u32>) -> Vec<AuditEntry> {
+        self.audit_entries(self.audit_by_actor.get(&actor), from_index, limit)
+    }
+
+    pub fn get_audit_log_count(&self) -> u32 {
+        self.audit_log.len()
+    }
+}
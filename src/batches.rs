@@ -0,0 +1,243 @@
+use crate::*;
+
+// How far ahead `get_expiring_batches` looks when no window is given
+pub const DEFAULT_EXPIRY_WARNING_DAYS: u32 = 90;
+// Lot number of the batch recorded for the quantity given to `add_medicine`
+pub const OPENING_LOT_NUMBER: &str = "OPENING";
+
+// Whose stock a batch is part of
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum StockHolder {
+    // Sold by the owner through `buy_medicine` without a pharmacy
+    Catalog,
+    Pharmacy(u32),
+    // With the manufacturer or a distributor; not for sale
+    SupplyChain,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Batch {
+    pub(crate) id: u32,
+    pub(crate) medicine_id: u32,
+    pub(crate) holder: StockHolder,
+    pub(crate) lot_number: String,
+    pub(crate) manufactured_at: u64,
+    pub(crate) expires_at: u64,
+    // Units received, and units not yet dispensed
+    pub(crate) quantity: u128,
+    pub(crate) remaining: u128,
+    // Set when the lot is recalled; no further sales
+    pub(crate) recall_id: Option<u32>,
+    // Account currently holding the batch, see `get_provenance`
+    pub(crate) custodian: AccountId,
+    pub(crate) pending_transfer: Option<PendingTransfer>,
+    // Serial hashes registered for the batch's packs, see `register_serials`
+    pub(crate) serials_registered: u128,
+}
+
+impl Batch {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+impl Contract {
+    // Records a batch without touching the holder's stock totals. Its custody
+    // chain starts with `batch.custodian` at `location`.
+    pub(crate) fn insert_batch(&mut self, mut batch: Batch, location: String) -> u32 {
+        batch.id = self.no_of_batches;
+        self.index_batch(batch.holder, batch.medicine_id, batch.id, batch.expires_at);
+        self.provenance.insert(
+            batch.id,
+            vec![CustodyRecord {
+                custodian: batch.custodian.clone(),
+                from: None,
+                location,
+                handed_over_at: env::block_timestamp(),
+                accepted_at: env::block_timestamp(),
+            }],
+        );
+        self.batch_by_lot
+            .entry((batch.medicine_id, batch.lot_number.clone()))
+            .or_default()
+            .push(batch.id);
+
+        MediEvent::BatchAdded {
+            batch_id: batch.id,
+            medicine_id: batch.medicine_id,
+            lot_number: batch.lot_number.clone(),
+            quantity: U128(batch.quantity),
+            expires_at: batch.expires_at,
+        }
+        .emit();
+        let id = batch.id;
+        self.batches.insert(id, batch);
+        self.no_of_batches += 1;
+        id
+    }
+
+    pub(crate) fn assert_batch_details(&self, lot_number: &str, manufactured_at: u64, expires_at: u64, quantity: u128) {
+        require!(quantity > 0, "Quantity must be greater than 0.");
+        require!(!lot_number.trim().is_empty(), "Lot number must not be empty");
+        require!(manufactured_at < expires_at, "Batch must be manufactured before it expires");
+        require!(expires_at > env::block_timestamp(), "Batch has already expired");
+    }
+
+    pub(crate) fn index_batch(&mut self, holder: StockHolder, medicine_id: u32, batch_id: u32, expires_at: u64) {
+        let stock = self.stock_batches.entry((holder, medicine_id)).or_default();
+        // Kept in expiry order for FEFO dispensing
+        let position = stock.partition_point(|id| self.batches[id].expires_at <= expires_at);
+        stock.insert(position, batch_id);
+    }
+
+    pub(crate) fn unindex_batch(&mut self, holder: StockHolder, medicine_id: u32, batch_id: u32) {
+        if let Some(stock) = self.stock_batches.get_mut(&(holder, medicine_id)) {
+            stock.retain(|id| *id != batch_id);
+        }
+    }
+
+    // Takes `quantity` from the holder's unexpired, unrecalled batches, first
+    // expiring first, and returns how much came from each batch
+    pub(crate) fn dispense_batches(&mut self, holder: StockHolder, medicine_id: u32, quantity: u128) -> Vec<(u32, u128)> {
+        let now = env::block_timestamp();
+        let mut allocations = vec![];
+        let mut needed = quantity;
+        for batch_id in self.stock_batches.get(&(holder, medicine_id)).cloned().unwrap_or_default() {
+            if needed == 0 {
+                break;
+            }
+            let batch = self.batches.get_mut(&batch_id).unwrap();
+            if batch.is_expired(now) || batch.recall_id.is_some() || batch.remaining == 0 {
+                continue;
+            }
+            let taken = batch.remaining.min(needed);
+            batch.remaining -= taken;
+            needed -= taken;
+            allocations.push((batch_id, taken));
+        }
+        require!(needed == 0, "Not enough unexpired stock");
+        allocations
+    }
+
+    pub(crate) fn restock_batches(&mut self, allocations: &[(u32, u128)]) {
+        for (batch_id, quantity) in allocations {
+            self.batches.get_mut(batch_id).expect("Batch not found").remaining += quantity;
+        }
+    }
+
+    // Units of the holder's batches that can still be sold; none once the
+    // whole medicine is recalled
+    pub(crate) fn sellable_quantity(&self, holder: StockHolder, medicine_id: u32) -> u128 {
+        if self.drugs.get(&medicine_id).and_then(|medicine| medicine.recall_id).is_some() {
+            return 0;
+        }
+        let now = env::block_timestamp();
+        self.stock_batches
+            .get(&(holder, medicine_id))
+            .into_iter()
+            .flatten()
+            .map(|id| &self.batches[id])
+            .filter(|batch| !batch.is_expired(now) && batch.recall_id.is_none())
+            .map(|batch| batch.remaining)
+            .sum()
+    }
+}
+
+#[near]
+impl Contract {
+    // Receives a batch into the catalog stock, or into the caller's pharmacy
+    // when `pharmacy_id` is set
+    pub fn add_batch(
+        &mut self,
+        medicine_id: u32,
+        lot_number: String,
+        manufactured_at: u64,
+        expires_at: u64,
+        quantity: u128,
+        pharmacy_id: Option<u32>,
+    ) -> u32 {
+        let holder = match pharmacy_id {
+            Some(pharmacy_id) => {
+                require!(self.caller_pharmacy_id() == pharmacy_id, "Only the pharmacy can stock itself");
+                require!(
+                    self.pharmacy_stock.contains_key(&(pharmacy_id, medicine_id)),
+                    "Set a price for the medicine before stocking it"
+                );
+                StockHolder::Pharmacy(pharmacy_id)
+            }
+            None => {
+                self.assert_role(&[Role::Admin, Role::Pharmacist]);
+                require!(self.drugs.contains_key(&medicine_id), "Medicine not found");
+                StockHolder::Catalog
+            }
+        };
+        self.assert_batch_details(&lot_number, manufactured_at, expires_at, quantity);
+        let initial_storage = env::storage_usage();
+        let location = match pharmacy_id {
+            Some(pharmacy_id) => self.pharmacies[&pharmacy_id].address.clone(),
+            None => self.drugs[&medicine_id].current_location.clone(),
+        };
+
+        let id = self.insert_batch(
+            Batch {
+                id: 0,
+                medicine_id,
+                holder,
+                lot_number,
+                manufactured_at,
+                expires_at,
+                quantity,
+                remaining: quantity,
+                recall_id: None,
+                custodian: env::predecessor_account_id(),
+                pending_transfer: None,
+                serials_registered: 0,
+            },
+            location,
+        );
+        match pharmacy_id {
+            Some(pharmacy_id) => self.add_pharmacy_stock(pharmacy_id, medicine_id, quantity),
+            None => {
+                let medicine = self.drugs.get_mut(&medicine_id).unwrap();
+                medicine.quantity += quantity;
+                medicine.availability = true;
+                medicine.emit_stock_changed();
+            }
+        }
+
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+        id
+    }
+
+    pub fn get_batch(&self, batch_id: u32) -> Option<Batch> {
+        self.batches.get(&batch_id).cloned()
+    }
+
+    // Batches of a medicine held in the catalog, or by a pharmacy, in the
+    // order they will be dispensed
+    pub fn get_medicine_batches(&self, medicine_id: u32, pharmacy_id: Option<u32>) -> Vec<Batch> {
+        let holder = pharmacy_id.map_or(StockHolder::Catalog, StockHolder::Pharmacy);
+        self.stock_batches
+            .get(&(holder, medicine_id))
+            .into_iter()
+            .flatten()
+            .map(|id| self.batches[id].clone())
+            .collect()
+    }
+
+    // Admin view of batches with stock left that expire within `within_days`
+    // (90 by default), expired ones included, soonest first
+    pub fn get_expiring_batches(&self, within_days: Option<u32>, from_index: Option<u32>, limit: Option<u32>) -> Vec<Batch> {
+        let horizon = env::block_timestamp()
+            .saturating_add(within_days.unwrap_or(DEFAULT_EXPIRY_WARNING_DAYS) as u64 * NANOS_PER_DAY);
+        let mut batches: Vec<&Batch> = self
+            .batches
+            .values()
+            .filter(|batch| batch.remaining > 0 && batch.expires_at <= horizon)
+            .collect();
+        batches.sort_by_key(|batch| batch.expires_at);
+        paginate(batches.into_iter(), from_index, limit)
+    }
+}
@@ -0,0 +1,208 @@
+use crate::*;
+
+pub const MINUTES_PER_DAY: u32 = 24 * 60;
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+// Longest date range `get_available_slots` expands in one call
+pub const MAX_SLOT_QUERY_DAYS: u32 = 31;
+
+// A period on one day. Dates count days since 1970-01-01 and times are
+// minutes after midnight, both in UTC.
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Slot {
+    pub(crate) date: u32,
+    pub(crate) start_minute: u32,
+    pub(crate) end_minute: u32,
+}
+
+impl Slot {
+    pub fn overlaps(&self, other: &Slot) -> bool {
+        self.date == other.date && self.start_minute < other.end_minute && other.start_minute < self.end_minute
+    }
+
+    // Block timestamp at which the slot starts
+    pub fn starts_at(&self) -> u64 {
+        (self.date as u64 * MINUTES_PER_DAY as u64 + self.start_minute as u64) * NANOS_PER_MINUTE
+    }
+
+    pub fn ends_at(&self) -> u64 {
+        (self.date as u64 * MINUTES_PER_DAY as u64 + self.end_minute as u64) * NANOS_PER_MINUTE
+    }
+}
+
+// 0 is Sunday; 1970-01-01 was a Thursday
+pub fn weekday(date: u32) -> u32 {
+    (date + 4) % 7
+}
+
+// Recurring hours a doctor is available on one day of the week
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct AvailabilityWindow {
+    pub(crate) weekday: u32,
+    pub(crate) start_minute: u32,
+    pub(crate) end_minute: u32,
+}
+
+// Days, inclusive, on which the weekly availability does not apply
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct AvailabilityException {
+    pub(crate) from_date: u32,
+    pub(crate) to_date: u32,
+    pub(crate) reason: String,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct DoctorCalendar {
+    pub(crate) slot_minutes: u32,
+    pub(crate) windows: Vec<AvailabilityWindow>,
+    pub(crate) exceptions: Vec<AvailabilityException>,
+}
+
+impl DoctorCalendar {
+    // Each window of the weekday cut into `slot_minutes` pieces from its start
+    pub(crate) fn slots_on(&self, date: u32) -> Vec<Slot> {
+        if self.exceptions.iter().any(|exception| exception.from_date <= date && date <= exception.to_date) {
+            return vec![];
+        }
+        let mut slots = vec![];
+        for window in self.windows.iter().filter(|window| window.weekday == weekday(date)) {
+            let mut start_minute = window.start_minute;
+            while start_minute + self.slot_minutes <= window.end_minute {
+                slots.push(Slot {
+                    date,
+                    start_minute,
+                    end_minute: start_minute + self.slot_minutes,
+                });
+                start_minute += self.slot_minutes;
+            }
+        }
+        slots.sort_by_key(|slot| slot.start_minute);
+        slots
+    }
+}
+
+impl Contract {
+    pub(crate) fn caller_doctor_id(&self) -> u32 {
+        self.assert_role(&[Role::Doctor]);
+        *self
+            .doctor_by_account
+            .get(&env::predecessor_account_id())
+            .expect("Caller has no doctor profile")
+    }
+
+    // Books `slot` for `appointment_id`. Booking and the overlap check happen
+    // in the same call, so two patients can never hold overlapping slots.
+    pub(crate) fn reserve_slot(&mut self, doctor_id: u32, slot: Slot, appointment_id: u32) {
+        require!(slot.starts_at() > env::block_timestamp(), "Slot is in the past");
+        let calendar = self.calendars.get(&doctor_id).expect("Doctor has not published availability");
+        require!(calendar.slots_on(slot.date).contains(&slot), "Slot is not offered by the doctor");
+
+        let booked = self.booked_slots.entry((doctor_id, slot.date)).or_default();
+        require!(!booked.iter().any(|(_, taken)| taken.overlaps(&slot)), "Slot is already booked");
+        booked.push((appointment_id, slot));
+    }
+
+    pub(crate) fn release_slot(&mut self, doctor_id: u32, slot: Slot, appointment_id: u32) {
+        if let Some(booked) = self.booked_slots.get_mut(&(doctor_id, slot.date)) {
+            booked.retain(|(id, _)| *id != appointment_id);
+            if booked.is_empty() {
+                self.booked_slots.remove(&(doctor_id, slot.date));
+            }
+        }
+    }
+
+    fn update_calendar(&mut self, doctor_id: u32, update: impl FnOnce(&mut DoctorCalendar)) {
+        let initial_storage = env::storage_usage();
+        let calendar = self.calendars.entry(doctor_id).or_insert_with(|| DoctorCalendar {
+            slot_minutes: 30,
+            windows: vec![],
+            exceptions: vec![],
+        });
+        update(calendar);
+
+        MediEvent::AvailabilityUpdated { doctor_id }.emit();
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
+}
+
+#[near]
+impl Contract {
+    // Replaces the caller's weekly hours; exceptions are kept
+    pub fn set_weekly_availability(&mut self, slot_minutes: u32, windows: Vec<AvailabilityWindow>) {
+        let doctor_id = self.caller_doctor_id();
+        require!(slot_minutes > 0, "Slot length must be positive");
+        for (index, window) in windows.iter().enumerate() {
+            require!(window.weekday < 7, "Weekday must be between 0 (Sunday) and 6");
+            require!(
+                window.start_minute < window.end_minute && window.end_minute <= MINUTES_PER_DAY,
+                "Availability window must start before it ends, within the day"
+            );
+            require!(
+                !windows[..index].iter().any(|other| other.weekday == window.weekday
+                    && window.start_minute < other.end_minute
+                    && other.start_minute < window.end_minute),
+                "Availability windows must not overlap"
+            );
+        }
+
+        self.update_calendar(doctor_id, |calendar| {
+            calendar.slot_minutes = slot_minutes;
+            calendar.windows = windows;
+        });
+    }
+
+    // Holidays and leave; no slots are offered from `from_date` to `to_date`
+    pub fn add_availability_exception(&mut self, from_date: u32, to_date: u32, reason: String) {
+        let doctor_id = self.caller_doctor_id();
+        require!(from_date <= to_date, "Exception must not end before it starts");
+
+        self.update_calendar(doctor_id, |calendar| {
+            calendar.exceptions.push(AvailabilityException { from_date, to_date, reason });
+        });
+    }
+
+    pub fn remove_availability_exception(&mut self, from_date: u32, to_date: u32) {
+        let doctor_id = self.caller_doctor_id();
+        let calendar = self.calendars.get(&doctor_id).expect("Doctor has not published availability");
+        require!(
+            calendar.exceptions.iter().any(|exception| exception.from_date == from_date && exception.to_date == to_date),
+            "Exception not found"
+        );
+
+        self.update_calendar(doctor_id, |calendar| {
+            calendar
+                .exceptions
+                .retain(|exception| exception.from_date != from_date || exception.to_date != to_date);
+        });
+    }
+
+    pub fn get_availability(&self, doctor_id: u32) -> Option<DoctorCalendar> {
+        self.calendars.get(&doctor_id).cloned()
+    }
+
+    // Unbooked future slots of the doctor from `from_date` to `to_date`, inclusive
+    pub fn get_available_slots(&self, doctor_id: u32, from_date: u32, to_date: u32) -> Vec<Slot> {
+        require!(from_date <= to_date, "Date range must not end before it starts");
+        require!(
+            to_date - from_date < MAX_SLOT_QUERY_DAYS,
+            format!("Date range is limited to {} days", MAX_SLOT_QUERY_DAYS)
+        );
+        let Some(calendar) = self.calendars.get(&doctor_id) else {
+            return vec![];
+        };
+        let now = env::block_timestamp();
+
+        (from_date..=to_date)
+            .flat_map(|date| {
+                let booked = self.booked_slots.get(&(doctor_id, date));
+                calendar.slots_on(date).into_iter().filter(move |slot| {
+                    slot.starts_at() > now
+                        && !booked.is_some_and(|booked| booked.iter().any(|(_, taken)| taken.overlaps(slot)))
+                })
+            })
+            .collect()
+    }
+}
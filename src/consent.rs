@@ -0,0 +1,171 @@
+use crate::*;
+
+// Lifetime of the grant created when a patient shares their medical history
+// while booking an appointment: 30 days, in nanoseconds
+pub const APPOINTMENT_CONSENT_DURATION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Access a patient has granted one doctor to their medical history
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Consent {
+    doctor_id: u32,
+    read: bool,
+    write: bool,
+    granted_at: u64,
+    // Block timestamp at which the grant lapses; `None` lasts until revoked
+    expires_at: Option<u64>,
+}
+
+impl Consent {
+    fn allows(&self, access: Access, now: u64) -> bool {
+        let scope = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        scope && self.expires_at.map_or(true, |expires_at| now < expires_at)
+    }
+}
+
+impl Contract {
+    pub(crate) fn caller_patient_id(&self) -> u32 {
+        *self
+            .patient_by_account
+            .get(&env::predecessor_account_id())
+            .expect("Caller has no patient profile")
+    }
+
+    pub(crate) fn consent_allows(&self, patient_id: u32, doctor_id: u32, access: Access) -> bool {
+        let now = env::block_timestamp();
+        self.consents.get(&patient_id).is_some_and(|consents| {
+            consents
+                .iter()
+                .any(|consent| consent.doctor_id == doctor_id && consent.allows(access, now))
+        })
+    }
+
+    // Patients may always read their own history; doctors need an active
+    // grant for the requested access.
+    pub(crate) fn assert_medical_access(&self, patient_id: u32, access: Access) {
+        let caller_id = env::predecessor_account_id();
+        let patient = self.patients.get(&patient_id).expect("Patient not found");
+        if access == Access::Read && patient.account_id == caller_id {
+            return;
+        }
+        let granted = self.has_role_internal(&caller_id, Role::Doctor)
+            && self
+                .doctor_by_account
+                .get(&caller_id)
+                .is_some_and(|doctor_id| self.consent_allows(patient_id, *doctor_id, access));
+        require!(
+            granted,
+            format!("Caller has no {:?} consent for patient {}", access, patient_id)
+        );
+    }
+
+    // Replaces any earlier grant the patient gave the same doctor
+    pub(crate) fn internal_grant_consent(
+        &mut self,
+        patient_id: u32,
+        doctor_id: u32,
+        read: bool,
+        write: bool,
+        expires_at: Option<u64>,
+    ) {
+        let consents = self.consents.entry(patient_id).or_default();
+        consents.retain(|consent| consent.doctor_id != doctor_id);
+        consents.push(Consent {
+            doctor_id,
+            read,
+            write,
+            granted_at: env::block_timestamp(),
+            expires_at,
+        });
+        self.record_audit(Some(patient_id), AuditAction::ConsentGranted);
+        MediEvent::ConsentGranted {
+            patient_id,
+            doctor_id,
+            read,
+            write,
+            expires_at,
+        }
+        .emit();
+    }
+
+    // Grant created by booking an appointment. It only ever widens what the
+    // doctor already holds, so an open-ended grant is never cut short.
+    pub(crate) fn grant_appointment_consent(&mut self, patient_id: u32, doctor_id: u32) {
+        let now = env::block_timestamp();
+        let lapses_at = now + APPOINTMENT_CONSENT_DURATION;
+        let active = self
+            .consents
+            .get(&patient_id)
+            .and_then(|consents| consents.iter().find(|consent| consent.doctor_id == doctor_id))
+            .filter(|consent| consent.expires_at.map_or(true, |expires_at| now < expires_at));
+        let expires_at = match active {
+            Some(consent) => consent.expires_at.map(|expires_at| expires_at.max(lapses_at)),
+            None => Some(lapses_at),
+        };
+        self.internal_grant_consent(patient_id, doctor_id, true, true, expires_at);
+    }
+}
+
+#[near]
+impl Contract {
+    // Called by a patient to let `doctor_id` read and/or append to their
+    // medical history until `expires_at` (a block timestamp) or revocation.
+    // `wrapped_keys` opens the patient's encrypted payloads to the doctor.
+    pub fn grant_consent(
+        &mut self,
+        doctor_id: u32,
+        read: bool,
+        write: bool,
+        expires_at: Option<u64>,
+        wrapped_keys: Option<Vec<RewrappedKey>>,
+    ) {
+        let patient_id = self.caller_patient_id();
+        require!(read || write, "Consent must grant read or write access");
+        require!(self.doctors.contains_key(&doctor_id), "Doctor not found");
+        require!(
+            expires_at.map_or(true, |expires_at| expires_at > env::block_timestamp()),
+            "Consent expiry must be in the future"
+        );
+        let initial_storage = env::storage_usage();
+
+        self.internal_grant_consent(patient_id, doctor_id, read, write, expires_at);
+        if let Some(wrapped_keys) = wrapped_keys {
+            self.internal_share_encrypted(patient_id, doctor_id, wrapped_keys);
+        }
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
+
+    pub fn revoke_consent(&mut self, doctor_id: u32) {
+        let patient_id = self.caller_patient_id();
+        let consents = self.consents.get_mut(&patient_id).expect("No consent to revoke");
+        let before = consents.len();
+        consents.retain(|consent| consent.doctor_id != doctor_id);
+        require!(consents.len() != before, "No consent to revoke");
+        if consents.is_empty() {
+            self.consents.remove(&patient_id);
+        }
+        self.internal_unshare_encrypted(patient_id, doctor_id);
+
+        self.record_audit(Some(patient_id), AuditAction::ConsentRevoked);
+        MediEvent::ConsentRevoked { patient_id, doctor_id }.emit();
+    }
+
+    // Grants a patient has given, including expired ones
+    pub fn get_consents(&self, patient_id: u32) -> Vec<Consent> {
+        self.consents.get(&patient_id).cloned().unwrap_or_default()
+    }
+
+    pub fn has_consent(&self, patient_id: u32, doctor_id: u32, access: Access) -> bool {
+        self.consent_allows(patient_id, doctor_id, access)
+    }
+}
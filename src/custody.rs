@@ -0,0 +1,162 @@
+use crate::*;
+
+// One step of a batch's custody chain. Records are only ever appended.
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct CustodyRecord {
+    pub(crate) custodian: AccountId,
+    // Previous custodian; `None` where the batch was first recorded
+    pub(crate) from: Option<AccountId>,
+    pub(crate) location: String,
+    pub(crate) handed_over_at: u64,
+    pub(crate) accepted_at: u64,
+}
+
+// A hand-off the current custodian started and the receiver has not
+// accepted yet
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct PendingTransfer {
+    pub(crate) to: AccountId,
+    pub(crate) location: String,
+    pub(crate) initiated_at: u64,
+}
+
+impl Contract {
+    // A batch accepted by an approved pharmacy joins its stock, one accepted
+    // by the owner joins the catalog; anyone else is a distributor.
+    fn receive_batch(&mut self, batch_id: u32, receiver: &AccountId) {
+        let batch = &self.batches[&batch_id];
+        let (medicine_id, quantity, expires_at) = (batch.medicine_id, batch.remaining, batch.expires_at);
+        let pharmacy_id = self
+            .pharmacy_by_account
+            .get(receiver)
+            .copied()
+            .filter(|id| self.pharmacies[id].is_approved);
+        let holder = match pharmacy_id {
+            Some(pharmacy_id) => {
+                require!(
+                    self.pharmacy_stock.contains_key(&(pharmacy_id, medicine_id)),
+                    "Set a price for the medicine before stocking it"
+                );
+                self.add_pharmacy_stock(pharmacy_id, medicine_id, quantity);
+                StockHolder::Pharmacy(pharmacy_id)
+            }
+            None if *receiver == self.owner => {
+                let medicine = self.drugs.get_mut(&medicine_id).expect("Medicine not found");
+                medicine.quantity += quantity;
+                medicine.availability = true;
+                medicine.emit_stock_changed();
+                StockHolder::Catalog
+            }
+            None => return,
+        };
+        self.unindex_batch(StockHolder::SupplyChain, medicine_id, batch_id);
+        self.index_batch(holder, medicine_id, batch_id, expires_at);
+        self.batches.get_mut(&batch_id).unwrap().holder = holder;
+    }
+}
+
+#[near]
+impl Contract {
+    // Called by a medicine's manufacturer to record a batch it produced. The
+    // batch is not for sale until a pharmacy or the owner accepts it.
+    pub fn register_batch(
+        &mut self,
+        medicine_id: u32,
+        lot_number: String,
+        manufactured_at: u64,
+        expires_at: u64,
+        quantity: u128,
+        location: String,
+    ) -> u32 {
+        require!(
+            self.caller_is_manufacturer(medicine_id),
+            "Only the medicine's manufacturer can register its batches"
+        );
+        self.assert_batch_details(&lot_number, manufactured_at, expires_at, quantity);
+        let initial_storage = env::storage_usage();
+
+        let id = self.insert_batch(
+            Batch {
+                id: 0,
+                medicine_id,
+                holder: StockHolder::SupplyChain,
+                lot_number,
+                manufactured_at,
+                expires_at,
+                quantity,
+                remaining: quantity,
+                recall_id: None,
+                custodian: env::predecessor_account_id(),
+                pending_transfer: None,
+                serials_registered: 0,
+            },
+            location,
+        );
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+        id
+    }
+
+    // Started by the current custodian; custody changes once `to` accepts
+    pub fn transfer_batch(&mut self, batch_id: u32, to: AccountId, location: String) {
+        let caller_id = env::predecessor_account_id();
+        let batch = self.batches.get_mut(&batch_id).expect("Batch not found");
+        require!(batch.custodian == caller_id, "Only the current custodian can hand the batch over");
+        require!(batch.holder == StockHolder::SupplyChain, "Batch is already stocked for sale");
+        require!(batch.pending_transfer.is_none(), "Batch already has a pending transfer");
+        require!(to != caller_id, "Batch is already held by the receiver");
+        let initial_storage = env::storage_usage();
+
+        batch.pending_transfer = Some(PendingTransfer {
+            to: to.clone(),
+            location,
+            initiated_at: env::block_timestamp(),
+        });
+        MediEvent::BatchTransferInitiated {
+            batch_id,
+            from: caller_id.clone(),
+            to: to.clone(),
+        }
+        .emit();
+        self.notify(to, format!("Batch {} is waiting for you to accept it", batch_id));
+        self.charge_storage(&caller_id, initial_storage);
+    }
+
+    pub fn cancel_batch_transfer(&mut self, batch_id: u32) {
+        let batch = self.batches.get_mut(&batch_id).expect("Batch not found");
+        require!(batch.custodian == env::predecessor_account_id(), "Only the current custodian can cancel the transfer");
+        require!(batch.pending_transfer.take().is_some(), "Batch has no pending transfer");
+    }
+
+    // Called by the receiver named in the pending transfer
+    pub fn accept_batch(&mut self, batch_id: u32) {
+        let caller_id = env::predecessor_account_id();
+        let batch = self.batches.get_mut(&batch_id).expect("Batch not found");
+        let transfer = batch.pending_transfer.take().expect("Batch has no pending transfer");
+        require!(transfer.to == caller_id, "Batch is not being handed to the caller");
+        let from = std::mem::replace(&mut batch.custodian, caller_id.clone());
+        let initial_storage = env::storage_usage();
+
+        self.provenance.entry(batch_id).or_default().push(CustodyRecord {
+            custodian: caller_id.clone(),
+            from: Some(from.clone()),
+            location: transfer.location,
+            handed_over_at: transfer.initiated_at,
+            accepted_at: env::block_timestamp(),
+        });
+        self.receive_batch(batch_id, &caller_id);
+        MediEvent::BatchTransferred {
+            batch_id,
+            from,
+            to: caller_id.clone(),
+        }
+        .emit();
+        self.charge_storage(&caller_id, initial_storage);
+    }
+
+    // Every custodian of the batch, oldest first
+    pub fn get_provenance(&self, batch_id: u32) -> Vec<CustodyRecord> {
+        self.provenance.get(&batch_id).cloned().unwrap_or_default()
+    }
+}
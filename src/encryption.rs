@@ -0,0 +1,189 @@
+use crate::*;
+
+pub const MAX_CIPHERTEXT_LENGTH: usize = 16_000;
+pub const MAX_WRAPPED_KEYS: usize = 20;
+pub const MAX_WRAPPED_KEY_LENGTH: usize = 1_000;
+
+// The content key of a payload, encrypted to one reader's public key
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct WrappedKey {
+    pub(crate) account_id: AccountId,
+    // Registry key the content key was wrapped under, so readers can tell
+    // when a rotation needs a fresh share
+    pub(crate) public_key: String,
+    pub(crate) wrapped_key: String,
+}
+
+// Data encrypted client side with a random content key. The contract never
+// sees the content key or the plaintext.
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct EncryptedPayload {
+    pub(crate) ciphertext: String,
+    pub(crate) wrapped_keys: Vec<WrappedKey>,
+}
+
+// A content key the patient re-wrapped for a doctor off-chain. `entry_id`
+// names a medical record; `None` stands for the patient's encrypted profile.
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct RewrappedKey {
+    pub(crate) entry_id: Option<u32>,
+    pub(crate) wrapped_key: String,
+}
+
+impl EncryptedPayload {
+    fn set_wrapped_key(&mut self, wrapped_key: WrappedKey) {
+        self.wrapped_keys.retain(|key| key.account_id != wrapped_key.account_id);
+        self.wrapped_keys.push(wrapped_key);
+    }
+}
+
+impl Contract {
+    fn registered_key(&self, account_id: &AccountId) -> String {
+        self.encryption_keys
+            .get(account_id)
+            .cloned()
+            .unwrap_or_else(|| env::panic_str(&format!("{} has not registered an encryption key", account_id)))
+    }
+
+    // Every wrapped key must use its reader's registered key and the patient
+    // must always be among the readers. Also bounds the payload's size.
+    pub(crate) fn assert_payload_readers(&self, patient_id: u32, payload: &EncryptedPayload) {
+        require!(
+            payload.ciphertext.len() <= MAX_CIPHERTEXT_LENGTH,
+            format!("Ciphertext is limited to {} bytes", MAX_CIPHERTEXT_LENGTH)
+        );
+        require!(
+            payload.wrapped_keys.len() <= MAX_WRAPPED_KEYS,
+            format!("A payload can have at most {} wrapped keys", MAX_WRAPPED_KEYS)
+        );
+        for key in &payload.wrapped_keys {
+            require!(
+                key.wrapped_key.len() <= MAX_WRAPPED_KEY_LENGTH,
+                format!("Wrapped keys are limited to {} bytes", MAX_WRAPPED_KEY_LENGTH)
+            );
+            require!(
+                key.public_key == self.registered_key(&key.account_id),
+                format!("Key for {} is not wrapped under its registered encryption key", key.account_id)
+            );
+        }
+        let patient_account = &self.patients.get(&patient_id).expect("Patient not found").account_id;
+        require!(
+            payload.wrapped_keys.iter().any(|key| &key.account_id == patient_account),
+            "Encrypted payload must be readable by the patient"
+        );
+    }
+
+    // Adds the doctor's wrapped keys to the patient's payloads; the doctor
+    // needs read consent and a registered encryption key.
+    pub(crate) fn internal_share_encrypted(&mut self, patient_id: u32, doctor_id: u32, keys: Vec<RewrappedKey>) {
+        require!(self.consent_allows(patient_id, doctor_id, Access::Read), "Doctor has no read consent");
+        let account_id = self.doctors.get(&doctor_id).expect("Doctor not found").account_id.clone();
+        let public_key = self.registered_key(&account_id);
+
+        for key in keys {
+            let wrapped_key = WrappedKey {
+                account_id: account_id.clone(),
+                public_key: public_key.clone(),
+                wrapped_key: key.wrapped_key,
+            };
+            let payload = match key.entry_id {
+                Some(entry_id) => {
+                    let entry = self.medical_records.get_mut(&entry_id).expect("Medical record not found");
+                    require!(entry.patient_id == patient_id, "Medical record belongs to another patient");
+                    entry.encrypted.as_mut().expect("Medical record is not encrypted")
+                }
+                None => self.encrypted_profiles.get_mut(&patient_id).expect("Patient has no encrypted profile"),
+            };
+            payload.set_wrapped_key(wrapped_key);
+        }
+        self.record_audit(Some(patient_id), AuditAction::EncryptedRecordsShared);
+    }
+
+    // Drops a revoked doctor's wrapped keys from the patient's payloads
+    pub(crate) fn internal_unshare_encrypted(&mut self, patient_id: u32, doctor_id: u32) {
+        let Some(doctor) = self.doctors.get(&doctor_id) else {
+            return;
+        };
+        let account_id = doctor.account_id.clone();
+        let entry_ids = self.medical_histories.get(&patient_id).cloned().unwrap_or_default();
+        for entry_id in entry_ids {
+            if let Some(payload) = self.medical_records.get_mut(&entry_id).and_then(|entry| entry.encrypted.as_mut()) {
+                payload.wrapped_keys.retain(|key| key.account_id != account_id);
+            }
+        }
+        if let Some(payload) = self.encrypted_profiles.get_mut(&patient_id) {
+            payload.wrapped_keys.retain(|key| key.account_id != account_id);
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    // Publishes the caller's encryption public key. Payloads wrapped under a
+    // previous key keep naming it until they are shared again.
+    pub fn set_encryption_key(&mut self, public_key: String) {
+        require!(!public_key.is_empty(), "Public key must not be empty");
+        let account_id = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+
+        self.encryption_keys.insert(account_id.clone(), public_key);
+        MediEvent::EncryptionKeyRegistered { account_id: account_id.clone() }.emit();
+        self.charge_storage(&account_id, initial_storage);
+    }
+
+    pub fn get_encryption_key(&self, account_id: AccountId) -> Option<String> {
+        self.encryption_keys.get(&account_id).cloned()
+    }
+
+    // Called by a patient to store their profile encrypted instead of in the
+    // plaintext `Patient` fields
+    pub fn set_encrypted_profile(&mut self, payload: EncryptedPayload) {
+        let patient_id = self.caller_patient_id();
+        self.assert_payload_readers(patient_id, &payload);
+        let initial_storage = env::storage_usage();
+
+        self.encrypted_profiles.insert(patient_id, payload);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
+
+    pub fn get_encrypted_profile(&mut self, patient_id: u32) -> Option<EncryptedPayload> {
+        self.assert_medical_access(patient_id, Access::Read);
+        self.record_read_audit(patient_id);
+        self.encrypted_profiles.get(&patient_id).cloned()
+    }
+
+    // Called by a patient to hand a consented doctor content keys re-wrapped
+    // under the doctor's registered key
+    pub fn share_encrypted_records(&mut self, doctor_id: u32, keys: Vec<RewrappedKey>) {
+        let patient_id = self.caller_patient_id();
+        let initial_storage = env::storage_usage();
+
+        self.internal_share_encrypted(patient_id, doctor_id, keys);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
+
+    // Encrypted payloads of the patient that `doctor_id` cannot open yet.
+    // Entry ids, with `None` for the profile.
+    pub fn get_unshared_encrypted_records(&self, patient_id: u32, doctor_id: u32) -> Vec<Option<u32>> {
+        let account_id = &self.doctors.get(&doctor_id).expect("Doctor not found").account_id;
+        let readable = |payload: &EncryptedPayload| payload.wrapped_keys.iter().any(|key| &key.account_id == account_id);
+
+        let mut unshared: Vec<Option<u32>> = self
+            .encrypted_profiles
+            .get(&patient_id)
+            .filter(|payload| !readable(payload))
+            .map(|_| None)
+            .into_iter()
+            .collect();
+        for entry_id in self.medical_histories.get(&patient_id).into_iter().flatten() {
+            let entry = &self.medical_records[entry_id];
+            if entry.encrypted.as_ref().is_some_and(|payload| !readable(payload)) {
+                unshared.push(Some(*entry_id));
+            }
+        }
+        unshared
+    }
+}
@@ -0,0 +1,98 @@
+use crate::*;
+
+impl Contract {
+    // Pays out what is left in the appointment's escrow: the platform keeps
+    // its commission and the doctor is credited with the rest.
+    pub(crate) fn release_escrow(&mut self, id: u32) {
+        let appointment = self.appointments.get_mut(&id).expect("Appointment not found");
+        let amount = std::mem::take(&mut appointment.escrow);
+        if amount == 0 {
+            return;
+        }
+        let doctor_id = appointment.doctor_id;
+        let commission = amount * self.commission_percent as u128 / 100;
+        let earned = amount - commission;
+        self.escrow_held -= amount;
+        self.appointment_fees_collected += commission;
+
+        let doctor = self.doctors.get_mut(&doctor_id).expect("Doctor not found");
+        doctor.earnings += earned;
+        doctor.total_earned += earned;
+
+        MediEvent::EscrowReleased {
+            appointment_id: id,
+            doctor_id,
+            earned: U128(earned),
+            commission: U128(commission),
+        }
+        .emit();
+    }
+}
+
+#[near]
+impl Contract {
+    // Sends the caller's released earnings, all of them unless `amount` is set
+    pub fn withdraw_earnings(&mut self, amount: Option<u128>) -> Promise {
+        let caller_id = env::predecessor_account_id();
+        let doctor_id = *self.doctor_by_account.get(&caller_id).expect("Caller has no doctor profile");
+        let doctor = self.doctors.get_mut(&doctor_id).unwrap();
+        let amount = amount.unwrap_or(doctor.earnings);
+        require!(amount > 0, "Nothing to withdraw");
+        require!(amount <= doctor.earnings, "Amount exceeds the withdrawable earnings");
+
+        doctor.earnings -= amount;
+        doctor.total_withdrawn += amount;
+
+        Promise::new(caller_id)
+            .transfer(NearToken::from_yoctonear(amount))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .on_earnings_withdrawn(doctor_id, amount),
+            )
+    }
+
+    // Restores the earnings if the transfer did not go through
+    #[private]
+    pub fn on_earnings_withdrawn(
+        &mut self,
+        doctor_id: u32,
+        amount: u128,
+        #[callback_result] transfer: Result<(), PromiseError>,
+    ) -> bool {
+        if transfer.is_err() {
+            let doctor = self.doctors.get_mut(&doctor_id).expect("Doctor not found");
+            doctor.earnings += amount;
+            doctor.total_withdrawn -= amount;
+            return false;
+        }
+
+        MediEvent::EarningsWithdrawn {
+            doctor_id,
+            amount: U128(amount),
+        }
+        .emit();
+        true
+    }
+
+    // Fee still held for an appointment that has not been settled
+    pub fn get_escrow_balance(&self, appointment_id: u32) -> u128 {
+        self.appointments.get(&appointment_id).expect("Appointment not found").escrow
+    }
+
+    pub fn update_platform_commission(&mut self, percent: u32) {
+        self.assert_role(&[Role::Admin]);
+        require!(percent <= 100, "Commission is a percentage between 0 and 100");
+        self.commission_percent = percent;
+        MediEvent::FeeUpdated {
+            fee: FeeKind::Commission,
+            amount: U128(percent as u128),
+        }
+        .emit();
+        self.record_audit(None, AuditAction::FeeUpdated);
+    }
+
+    pub fn get_platform_commission(&self) -> u32 {
+        self.commission_percent
+    }
+}
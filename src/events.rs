@@ -0,0 +1,236 @@
+use crate::*;
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+
+// NEP-297 standard name and version reported in every `EVENT_JSON:` log
+pub const EVENT_STANDARD: &str = "medi";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum FeeKind {
+    Registration,
+    Appointment,
+    // Platform commission percentage on released appointment fees
+    Commission,
+}
+
+// Every state change the indexer can follow. Token amounts are encoded as
+// strings so they survive JSON number precision limits.
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "event", content = "data", rename_all = "snake_case")]
+pub enum MediEvent {
+    PatientRegistered {
+        patient_id: u32,
+        account_id: AccountId,
+    },
+    DoctorRegistered {
+        doctor_id: u32,
+        account_id: AccountId,
+    },
+    DoctorApproved {
+        doctor_id: u32,
+        account_id: AccountId,
+    },
+    MedicalHistoryUpdated {
+        patient_id: u32,
+        entry_id: u32,
+        actor: AccountId,
+    },
+    ConsentGranted {
+        patient_id: u32,
+        doctor_id: u32,
+        read: bool,
+        write: bool,
+        expires_at: Option<u64>,
+    },
+    ConsentRevoked {
+        patient_id: u32,
+        doctor_id: u32,
+    },
+    EncryptionKeyRegistered {
+        account_id: AccountId,
+    },
+    AvailabilityUpdated {
+        doctor_id: u32,
+    },
+    AppointmentStatusChanged {
+        appointment_id: u32,
+        patient_id: u32,
+        doctor_id: u32,
+        status: AppointmentStatus,
+        actor: AccountId,
+    },
+    AppointmentRefunded {
+        appointment_id: u32,
+        patient_id: u32,
+        amount: U128,
+    },
+    EscrowReleased {
+        appointment_id: u32,
+        doctor_id: u32,
+        earned: U128,
+        commission: U128,
+    },
+    EarningsWithdrawn {
+        doctor_id: u32,
+        amount: U128,
+    },
+    FeesWithdrawn {
+        owner: AccountId,
+        amount: U128,
+    },
+    CancellationPolicyUpdated {
+        full_refund_hours: u32,
+        late_refund_percent: u32,
+    },
+    AppointmentRated {
+        appointment_id: u32,
+        doctor_id: u32,
+        rating: u8,
+    },
+    PharmacyRegistered {
+        pharmacy_id: u32,
+        account_id: AccountId,
+    },
+    PharmacyApproved {
+        pharmacy_id: u32,
+        account_id: AccountId,
+    },
+    PharmacyStockChanged {
+        pharmacy_id: u32,
+        medicine_id: u32,
+        quantity: U128,
+        price: U128,
+    },
+    BatchAdded {
+        batch_id: u32,
+        medicine_id: u32,
+        lot_number: String,
+        quantity: U128,
+        expires_at: u64,
+    },
+    MedicineRecalled {
+        recall_id: u32,
+        medicine_id: u32,
+        lot_number: Option<String>,
+        reason: String,
+    },
+    BatchTransferInitiated {
+        batch_id: u32,
+        from: AccountId,
+        to: AccountId,
+    },
+    BatchTransferred {
+        batch_id: u32,
+        from: AccountId,
+        to: AccountId,
+    },
+    SerialsRegistered {
+        batch_id: u32,
+        count: u32,
+    },
+    SerialsDispensed {
+        order_id: u32,
+        count: u32,
+    },
+    PrescriptionCreated {
+        prescription_id: u32,
+        patient_id: u32,
+        doctor_id: u32,
+        medicine_id: u32,
+    },
+    MedicineAdded {
+        medicine_id: u32,
+        name: String,
+        price: U128,
+        quantity: U128,
+    },
+    MedicineScheduleUpdated {
+        medicine_id: u32,
+        requires_prescription: bool,
+        controlled_schedule: Option<u8>,
+    },
+    MedicineStockChanged {
+        medicine_id: u32,
+        quantity: U128,
+        availability: bool,
+    },
+    OrderPlaced {
+        order_id: u32,
+        patient_id: u32,
+        medicine_id: u32,
+        quantity: U128,
+        payment_amount: U128,
+    },
+    OrderStatusChanged {
+        order_id: u32,
+        status: OrderStatus,
+    },
+    FeeUpdated {
+        fee: FeeKind,
+        amount: U128,
+    },
+    OwnerChanged {
+        old_owner: AccountId,
+        new_owner: AccountId,
+    },
+    RoleGranted {
+        account_id: AccountId,
+        role: Role,
+    },
+    RoleRevoked {
+        account_id: AccountId,
+        role: Role,
+    },
+    NotificationSent {
+        account_id: AccountId,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a MediEvent,
+}
+
+impl MediEvent {
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_STANDARD_VERSION,
+            event: self,
+        };
+        env::log_str(&format!("EVENT_JSON:{}", serde_json::to_string(&log).unwrap()));
+    }
+}
+
+impl Appointment {
+    // Reports the latest step recorded in `history`
+    pub(crate) fn emit_status_changed(&self) {
+        let change = self.history.last().expect("Appointment has no history");
+        MediEvent::AppointmentStatusChanged {
+            appointment_id: self.id,
+            patient_id: self.patient_id,
+            doctor_id: self.doctor_id,
+            status: change.status,
+            actor: change.actor.clone(),
+        }
+        .emit();
+    }
+}
+
+impl Medicine {
+    pub(crate) fn emit_stock_changed(&self) {
+        MediEvent::MedicineStockChanged {
+            medicine_id: self.id,
+            quantity: U128(self.quantity),
+            availability: self.availability,
+        }
+        .emit();
+    }
+}
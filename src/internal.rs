@@ -4,15 +4,201 @@ use crate::*;
 
 //This will be include in the contract body
 impl Contract {
-    pub fn is_admin(&self) -> bool {
-        env::current_account_id() == self.owner
+    // Stores a notification for `user_address` on behalf of the contract itself
+    pub(crate) fn notify(&mut self, user_address: AccountId, message: String) {
+        let timestamp = env::block_timestamp();
+
+        let notification = Notification {
+            account_id: user_address.clone(),
+            message,
+            timestamp,
+        };
+
+        // Store the notification in the vector
+        self.notifications.push(notification);
+        self.no_of_notifications += 1;
+
+        MediEvent::NotificationSent { account_id: user_address }.emit();
+    }
+
+    // Profiles may be registered by their own account or by platform staff
+    pub(crate) fn assert_self_or_role(&self, account_id: &AccountId, roles: &[Role]) {
+        require!(
+            &env::predecessor_account_id() == account_id || self.caller_has_any_role(roles),
+            "Only the account itself or platform staff can register this profile"
+        );
+    }
+
+    // Sends back whatever was attached on top of `cost`
+    pub(crate) fn refund_excess(&self, account_id: &AccountId, deposit: u128, cost: u128) {
+        if deposit > cost {
+            Promise::new(account_id.clone()).transfer(NearToken::from_yoctonear(deposit - cost));
+        }
+    }
+
+    // Requires the attached deposit to cover `fee`, refunds the rest to the
+    // caller and returns the amount kept by the contract
+    pub(crate) fn collect_fee(&self, fee: u128) -> u128 {
+        let deposit = env::attached_deposit().as_yoctonear();
+        require!(deposit >= fee, format!("Attached deposit must cover the fee of {}", fee));
+        self.refund_excess(&env::predecessor_account_id(), deposit, fee);
+        fee
+    }
+
+    // Checks that the patient may buy `quantity` of the medicine and, for
+    // prescription-only medicines, uses up one fill of the prescription.
+    // Returns the prescription the purchase was made against.
+    pub(crate) fn fill_prescription(
+        &mut self,
+        prescription_id: Option<u32>,
+        patient_id: u32,
+        medicine_id: u32,
+        quantity: u128,
+    ) -> Option<u32> {
+        let medicine = self.drugs.get(&medicine_id).expect("Medicine not found");
+        if !medicine.needs_prescription() {
+            return None;
+        }
+        let id = prescription_id.expect("Medicine requires a prescription");
+        let prescription = self.prescriptions.get_mut(&id).expect("Prescription not found");
+        require!(
+            prescription.patient_id == patient_id && prescription.medicine_id == medicine_id,
+            "Prescription is not for this patient and medicine"
+        );
+        require!(prescription.expires_at > env::block_timestamp(), "Prescription has expired");
+        require!(prescription.fills_used <= prescription.refills, "Prescription has no refills left");
+        require!(
+            quantity <= prescription.quantity,
+            format!("Quantity exceeds the {} units prescribed", prescription.quantity)
+        );
+        prescription.fills_used += 1;
+        Some(id)
+    }
+
+    fn appointment_party(&self, appointment: &Appointment, account_id: &AccountId) -> Option<AppointmentParty> {
+        if self.patients.get(&appointment.patient_id).is_some_and(|patient| &patient.account_id == account_id) {
+            Some(AppointmentParty::Patient)
+        } else if self.doctors.get(&appointment.doctor_id).is_some_and(|doctor| &doctor.account_id == account_id) {
+            Some(AppointmentParty::Doctor)
+        } else {
+            None
+        }
+    }
+
+    // Account of the party that did not trigger the current call
+    pub(crate) fn other_appointment_party(&self, appointment: &Appointment) -> AccountId {
+        let patient_account = &self.patients.get(&appointment.patient_id).expect("Patient not found").account_id;
+        if patient_account == &env::predecessor_account_id() {
+            self.doctors.get(&appointment.doctor_id).expect("Doctor not found").account_id.clone()
+        } else {
+            patient_account.clone()
+        }
     }
 
-    pub fn is_user(&self) -> bool{
-        self.users.contains(&env::predecessor_account_id())
+    // The caller holds the Manufacturer role and is linked to the medicine
+    pub(crate) fn caller_is_manufacturer(&self, medicine_id: u32) -> bool {
+        let caller_id = env::predecessor_account_id();
+        let medicine = self.drugs.get(&medicine_id).expect("Medicine not found");
+        medicine.manufacturer_account.as_ref() == Some(&caller_id) && self.has_role_internal(&caller_id, Role::Manufacturer)
     }
 
-    pub fn is_owner_or_admin(&self) -> bool{
-        self.is_user() || self.is_admin()
+    pub(crate) fn caller_is_appointment_doctor(&self, appointment: &Appointment) -> bool {
+        self.appointment_party(appointment, &env::predecessor_account_id()) == Some(AppointmentParty::Doctor)
     }
+
+    // Last moment a patient can cancel for a full refund
+    pub(crate) fn full_refund_cutoff(&self, appointment: &Appointment) -> u64 {
+        let notice = self.cancellation_policy.full_refund_hours as u64 * 3_600 * 1_000_000_000;
+        appointment.slot.starts_at().saturating_sub(notice)
+    }
+
+    // Whether the doctor let an appointment that was in `status` lapse: it was
+    // never confirmed before the slot started, or the patient was never
+    // checked in within `CHECK_IN_TIMEOUT_HOURS` of the slot ending
+    pub(crate) fn appointment_lapsed(&self, appointment: &Appointment, status: AppointmentStatus) -> bool {
+        let now = env::block_timestamp();
+        match status {
+            AppointmentStatus::Requested => now >= appointment.slot.starts_at(),
+            AppointmentStatus::Confirmed => {
+                now >= appointment.slot.ends_at() + CHECK_IN_TIMEOUT_HOURS * 3_600 * 1_000_000_000
+            }
+            _ => false,
+        }
+    }
+
+    // Part of the escrowed fee returned when the caller cancels `appointment`
+    pub(crate) fn cancellation_refund(&self, appointment: &Appointment) -> u128 {
+        if self.caller_is_appointment_doctor(appointment) || env::block_timestamp() < self.full_refund_cutoff(appointment) {
+            appointment.escrow
+        } else {
+            appointment.escrow * self.cancellation_policy.late_refund_percent as u128 / 100
+        }
+    }
+
+    // Returns `amount` of the escrowed appointment fee to the patient
+    pub(crate) fn refund_appointment(&mut self, id: u32, amount: u128) {
+        let appointment = self.appointments.get_mut(&id).expect("Appointment not found");
+        appointment.escrow -= amount;
+        appointment.fee_paid -= amount;
+        appointment.fee_refunded += amount;
+        let patient_id = appointment.patient_id;
+        self.escrow_held -= amount;
+
+        let patient_account = self.patients.get(&patient_id).expect("Patient not found").account_id.clone();
+        Promise::new(patient_account).transfer(NearToken::from_yoctonear(amount));
+        MediEvent::AppointmentRefunded {
+            appointment_id: id,
+            patient_id,
+            amount: U128(amount),
+        }
+        .emit();
+    }
+
+    // Stores `appointment` under its id and lists it for both parties
+    pub(crate) fn insert_appointment(&mut self, appointment: Appointment) {
+        let id = appointment.id;
+        self.appointments_by_doctor.entry(appointment.doctor_id).or_default().push(id);
+        if self.patients.contains_key(&appointment.patient_id) {
+            self.appointments_by_patient.entry(appointment.patient_id).or_default().push(id);
+        }
+        self.appointments.insert(id, appointment);
+        self.no_of_appointments += 1;
+    }
+
+    // Moves an appointment to `next` after checking that the caller is one of
+    // the `allowed` parties and that the lifecycle permits the step.
+    pub(crate) fn transition_appointment(
+        &mut self,
+        id: u32,
+        next: AppointmentStatus,
+        allowed: &[AppointmentParty],
+    ) -> Appointment {
+        let caller_id = env::predecessor_account_id();
+        let appointment = self.appointments.get(&id).expect("Appointment not found");
+        let party = self.appointment_party(appointment, &caller_id);
+        require!(
+            party.is_some_and(|party| allowed.contains(&party)),
+            format!("Caller may not move appointment {} to {:?}", id, next)
+        );
+        require!(
+            appointment.status.can_transition_to(next),
+            format!("Cannot move appointment from {:?} to {:?}", appointment.status, next)
+        );
+
+        let appointment = self.appointments.get_mut(&id).unwrap();
+        appointment.status = next;
+        appointment.history.push(StatusChange {
+            status: next,
+            actor: caller_id,
+            timestamp: env::block_timestamp(),
+        });
+        appointment.emit_status_changed();
+        appointment.clone()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum AppointmentParty {
+    Patient,
+    Doctor,
 }
\ No newline at end of file
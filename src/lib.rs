@@ -1,17 +1,87 @@
-use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{env,log ,near, require,near_bindgen, AccountId, NearToken, PanicOnDefault, Promise};
-use near_sdk::store::{IterableSet,LookupSet,Vector,UnorderedSet,UnorderedMap};
-// use serde_json::json
-
+// Contract methods mirror the frontend forms field by field.
+#![allow(clippy::too_many_arguments)]
+
+use near_sdk::{env, log, near, require, AccountId, BorshStorageKey, Gas, NearToken, Promise, PromiseError};
+use near_sdk::json_types::U128;
+use near_sdk::store::{IterableMap, IterableSet, LookupMap, Vector};
+
+pub mod audit;
+pub mod batches;
+pub mod calendar;
+pub mod consent;
+pub mod custody;
+pub mod encryption;
+pub mod escrow;
+pub mod events;
 pub mod internal;
+pub mod migrate;
+pub mod pharmacy;
+pub mod rbac;
+pub mod recalls;
+pub mod records;
+pub mod reviews;
+pub mod serials;
+pub mod storage;
 pub mod utils;
+pub use crate::audit::{AuditAction, AuditEntry};
+pub use crate::batches::{Batch, StockHolder};
+pub use crate::calendar::{AvailabilityException, AvailabilityWindow, DoctorCalendar, Slot};
+pub use crate::consent::{Access, Consent};
+pub use crate::custody::{CustodyRecord, PendingTransfer};
+pub use crate::encryption::{EncryptedPayload, RewrappedKey, WrappedKey};
+pub use crate::events::{FeeKind, MediEvent};
+pub use crate::pharmacy::{Pharmacy, PharmacyInput, PharmacyOffer, PharmacyStock};
+pub use crate::rbac::Role;
+pub use crate::recalls::Recall;
+pub use crate::records::{MedicalRecordEntry, RecordKind};
+pub use crate::reviews::Review;
+pub use crate::serials::{SerialRecord, SerialStatus};
+pub use crate::storage::{StorageAccount, StorageBalance, StorageBalanceBounds};
 pub use crate::utils::*;
+use crate::internal::AppointmentParty;
 
 #[near(serializers = [json, borsh])]
-#[derive(Clone,PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum AppointmentStatus {
-    Pending,
+    Requested,
+    Confirmed,
+    CheckedIn,
     Completed,
+    Cancelled,
+    NoShow,
+    Rescheduled,
+}
+
+impl AppointmentStatus {
+    // Lifecycle: Requested -> Confirmed -> CheckedIn -> Completed, with
+    // Cancelled, NoShow and Rescheduled as the other ways out.
+    pub fn can_transition_to(&self, next: AppointmentStatus) -> bool {
+        use AppointmentStatus::*;
+        matches!(
+            (self, next),
+            (Requested, Confirmed)
+                | (Requested, Cancelled)
+                | (Requested, Rescheduled)
+                | (Confirmed, CheckedIn)
+                | (Confirmed, Cancelled)
+                | (Confirmed, NoShow)
+                | (Confirmed, Rescheduled)
+                | (CheckedIn, Completed)
+        )
+    }
+
+    pub fn is_closed(&self) -> bool {
+        use AppointmentStatus::*;
+        matches!(self, Completed | Cancelled | NoShow | Rescheduled)
+    }
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct StatusChange {
+    status: AppointmentStatus,
+    actor: AccountId,
+    timestamp: u64,
 }
 
 #[near(serializers = [json, borsh])]
@@ -51,8 +121,8 @@ pub struct Patient {
     profile_pic: String,
     account_id: AccountId,
     message: String,
-    medical_history: Vec<String>,
-    bought_medicine: Vec<i32>,
+    bought_medicine: Vec<u32>,
+    registration_fee_paid: u128,
 }
 
 #[near(serializers = [json, borsh])]
@@ -98,9 +168,18 @@ pub struct Doctor {
     account_id: AccountId,
     profile_pic: String,
     bio: String,
+    // Completed appointments, and those rated 4 or better
     appointment_counts: i32,
     successful_treaments: i32,
     is_approved: bool,
+    registration_fee_paid: u128,
+    // Average rating is `rating_sum / rating_count`
+    rating_sum: u32,
+    rating_count: u32,
+    // Released appointment fees not withdrawn yet
+    earnings: u128,
+    total_earned: u128,
+    total_withdrawn: u128,
 }
 
 #[near(serializers = [json, borsh])]
@@ -123,6 +202,35 @@ pub struct Medicine {
     image: String,
     description: String,
     availability: bool,
+    requires_prescription: bool,
+    // Controlled-substance schedule (e.g. 2 for Schedule II); scheduled
+    // medicines always require a prescription
+    controlled_schedule: Option<u8>,
+    // Account allowed to recall the medicine, see `set_medicine_manufacturer`
+    manufacturer_account: Option<AccountId>,
+    // Set once the whole medicine is recalled; no further sales
+    recall_id: Option<u32>,
+}
+
+impl Medicine {
+    pub fn needs_prescription(&self) -> bool {
+        self.requires_prescription || self.controlled_schedule.is_some()
+    }
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct PrescriptionInput {
+    medicine_id: u32,
+    patient_id: u32,
+    dosage: String,
+    frequency: String,
+    duration_days: u32,
+    quantity: u128,
+    refills: u32,
+    expires_at: u64,
+    instructions: String,
+    appointment_id: Option<u32>,
 }
 
 #[near(serializers = [json, borsh])]
@@ -133,6 +241,20 @@ pub struct Prescription {
     patient_id: u32,
     doctor_id: u32,
     date: u64,
+    // e.g. "500mg" taken "twice a day" for `duration_days`
+    dosage: String,
+    frequency: String,
+    duration_days: u32,
+    // Units that may be dispensed per fill
+    quantity: u128,
+    // Fills allowed after the first one
+    refills: u32,
+    // Block timestamp after which the prescription can no longer be filled
+    expires_at: u64,
+    // Purchases made against the prescription, at most `refills + 1`
+    fills_used: u32,
+    instructions: String,
+    appointment_id: Option<u32>,
 }
 
 #[near(serializers = [json, borsh])]
@@ -141,13 +263,17 @@ pub struct Appointment {
     id: u32,
     patient_id: u32,
     doctor_id: u32,
-    from: String,
-    to: String,
-    appointment_date: String,
+    slot: Slot,
     condition: String,
     status: AppointmentStatus,
     message: String,
-    is_open:bool,
+    history: Vec<StatusChange>,
+    rescheduled_from: Option<u32>,
+    rescheduled_to: Option<u32>,
+    fee_paid: u128,
+    fee_refunded: u128,
+    // Part of the fee held until the appointment is settled
+    escrow: u128,
 }
 
 #[near(serializers = [json, borsh])]
@@ -159,15 +285,66 @@ pub struct Message{
     message: String,
 }
 
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OrderStatus {
+    // Stock is reserved and the payment is on its way to the seller
+    Pending,
+    Paid,
+    // The payment could not be delivered; stock was restored and the patient refunded
+    Failed,
+}
+
 #[near(serializers = [json, borsh])]
 #[derive(Clone)]
 pub struct Order {
-     medicine_id: u32,
-     price: u128,
-     payment_amount: u64,
-     quantity: u128,
-     patient_id: u64,
-     date: u64,
+    id: u32,
+    medicine_id: u32,
+    patient_id: u32,
+    // Unit price and discount percentage at the time of purchase
+    price: u128,
+    discount: u128,
+    quantity: u128,
+    payment_amount: u128,
+    status: OrderStatus,
+    date: u64,
+    // Prescription whose fill this order consumed
+    prescription_id: Option<u32>,
+    // Pharmacy the order was routed to; `None` for the catalog stock
+    pharmacy_id: Option<u32>,
+    // Units dispensed from each batch, by batch id
+    batches: Vec<(u32, u128)>,
+    // Hashes scanned from the packs handed over, see `dispense_serials`
+    serials: Vec<String>,
+}
+
+// Fees collected by the contract since deployment. `appointment_fees` is the
+// platform commission on released appointment fees; `escrowed` is still held
+// for open appointments and not part of `total`. `withdrawable` is what the
+// owner can still take out with `withdraw_fees`.
+#[near(serializers = [json])]
+pub struct FeeTreasury {
+    registration_fees: u128,
+    appointment_fees: u128,
+    total: u128,
+    escrowed: u128,
+    withdrawn: u128,
+    withdrawable: u128,
+}
+
+// How long after a confirmed slot ends the doctor has to check the patient
+// in or record a no-show before the patient may cancel for a full refund
+pub const CHECK_IN_TIMEOUT_HOURS: u64 = 24;
+
+// Share of the appointment fee returned when an appointment is cancelled.
+// Doctor cancellations are always refunded in full and no-shows never are.
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct CancellationPolicy {
+    // Patients cancelling at least this many hours ahead get the full fee back
+    full_refund_hours: u32,
+    // Percentage refunded for later patient cancellations
+    late_refund_percent: u32,
 }
 
 #[near(serializers = [json, borsh])]
@@ -178,27 +355,141 @@ pub struct Notification {
     timestamp: u64,
 }
 
+// Prefixes for every persistent collection. Variants are serialized by
+// position, so new collections must only ever be appended at the end.
+#[near(serializers = [borsh])]
+#[derive(BorshStorageKey)]
+pub enum StorageKey {
+    // Retired: the admin set was replaced by `Roles`
+    Users,
+    Patients,
+    Doctors,
+    Drugs,
+    Prescriptions,
+    Appointments,
+    Notifications,
+    Orders,
+    Messages,
+    Roles,
+    PatientsByAccount,
+    DoctorsByAccount,
+    StorageAccounts,
+    Consents,
+    MedicalHistories,
+    AuditLog,
+    AuditByPatient,
+    AuditByActor,
+    MedicalRecords,
+    EncryptionKeys,
+    EncryptedProfiles,
+    Calendars,
+    BookedSlots,
+    Reviews,
+    ReviewsByDoctor,
+    Pharmacies,
+    PharmaciesByAccount,
+    PharmacyStock,
+    PharmacyMedicines,
+    Stockists,
+    Batches,
+    StockBatches,
+    BatchesByLot,
+    Recalls,
+    OrdersByMedicine,
+    Provenance,
+    Serials,
+    AuditPatientEntries { patient_id: u32 },
+    AuditActorEntries { account_hash: Vec<u8> },
+    OrdersByPatient,
+    PrescriptionsByPatient,
+    AppointmentsByPatient,
+    AppointmentsByDoctor,
+    Conversations,
+}
+
 #[near(contract_state)]
 // #[derive(Default, BorshDeserialize, BorshSerialize)]
 pub struct Contract {
     owner: AccountId,
-    users: IterableSet<AccountId>,
-    patients: Vector<Patient>,
+    roles: IterableMap<AccountId, Vec<Role>>,
+    patients: IterableMap<u32, Patient>,
     no_of_patients: u32,
-    doctors: Vector<Doctor>,
+    patient_by_account: LookupMap<AccountId, u32>,
+    doctors: IterableMap<u32, Doctor>,
     no_of_doctors: u32,
-    drugs: Vector<Medicine>,
+    doctor_by_account: LookupMap<AccountId, u32>,
+    drugs: IterableMap<u32, Medicine>,
     no_of_drugs: u32,
-    prescriptions: Vector<Prescription>,
+    prescriptions: IterableMap<u32, Prescription>,
     no_of_prescriptions: u32,
-    appointments: Vector<Appointment>,
+    appointments: IterableMap<u32, Appointment>,
     no_of_appointments: u32,
     notifications: Vector<Notification>,
     no_of_notifications: u32,
-    orders: Vector<Order>,
+    orders: IterableMap<u32, Order>,
+    no_of_orders: u32,
     messages: Vector<Message>,
     appointment_fee: u128,
     registration_fee: u128,
+    registration_fees_collected: u128,
+    appointment_fees_collected: u128,
+    // Collected fees already sent to the owner
+    fees_withdrawn: u128,
+    cancellation_policy: CancellationPolicy,
+    // Appointment fees held until their appointments are settled
+    escrow_held: u128,
+    // Percentage of each released appointment fee kept by the platform
+    commission_percent: u32,
+    storage_accounts: LookupMap<AccountId, StorageAccount>,
+    // Grants each patient has given, keyed by patient id
+    consents: LookupMap<u32, Vec<Consent>>,
+    // Ids of each patient's `medical_records`, kept apart from `patients` so
+    // profile views do not expose them
+    medical_histories: LookupMap<u32, Vec<u32>>,
+    medical_records: LookupMap<u32, MedicalRecordEntry>,
+    no_of_medical_records: u32,
+    // Encryption public key published by each account
+    encryption_keys: LookupMap<AccountId, String>,
+    encrypted_profiles: LookupMap<u32, EncryptedPayload>,
+    audit_log: Vector<AuditEntry>,
+    // Positions in `audit_log`, by patient and by acting account
+    audit_by_patient: LookupMap<u32, Vector<u32>>,
+    audit_by_actor: LookupMap<AccountId, Vector<u32>>,
+    calendars: LookupMap<u32, DoctorCalendar>,
+    // Appointments holding a slot, by doctor id and date
+    booked_slots: LookupMap<(u32, u32), Vec<(u32, Slot)>>,
+    // Keyed by appointment id
+    reviews: LookupMap<u32, Review>,
+    reviews_by_doctor: LookupMap<u32, Vec<u32>>,
+    pharmacies: IterableMap<u32, Pharmacy>,
+    no_of_pharmacies: u32,
+    pharmacy_by_account: LookupMap<AccountId, u32>,
+    // Keyed by pharmacy id and medicine id
+    pharmacy_stock: LookupMap<(u32, u32), PharmacyStock>,
+    // Medicines each pharmacy has stock records for
+    pharmacy_medicines: LookupMap<u32, Vec<u32>>,
+    // Pharmacies with stock records for each medicine
+    stockists: LookupMap<u32, Vec<u32>>,
+    batches: IterableMap<u32, Batch>,
+    no_of_batches: u32,
+    // Batch ids by holder and medicine id, soonest expiring first
+    stock_batches: LookupMap<(StockHolder, u32), Vec<u32>>,
+    // Batch ids by medicine id and lot number
+    batch_by_lot: LookupMap<(u32, String), Vec<u32>>,
+    recalls: Vector<Recall>,
+    orders_by_medicine: LookupMap<u32, Vec<u32>>,
+    // Custody chain of each batch, by batch id
+    provenance: LookupMap<u32, Vec<CustodyRecord>>,
+    // Keyed by serial hash
+    serials: LookupMap<String, SerialRecord>,
+    // Record ids by patient or doctor id, oldest first, so per-party views
+    // do not scan every record
+    orders_by_patient: LookupMap<u32, Vec<u32>>,
+    prescriptions_by_patient: LookupMap<u32, Vec<u32>>,
+    appointments_by_patient: LookupMap<u32, Vec<u32>>,
+    appointments_by_doctor: LookupMap<u32, Vec<u32>>,
+    // Positions in `messages`, by patient and doctor account
+    conversations: LookupMap<(AccountId, AccountId), Vec<u32>>,
 }
 
 // Implement the default method for Contract, initializing all collections
@@ -206,23 +497,68 @@ impl Default for Contract {
     fn default() -> Self {
         Self {
             owner: "medinear.testnet".parse().unwrap(),
-            users: IterableSet::new(b"s"),
-            patients: Vector::new(b"p"),
+            roles: IterableMap::new(StorageKey::Roles),
+            patients: IterableMap::new(StorageKey::Patients),
             no_of_patients: 0,
-            doctors: Vector::new(b"d"),
+            patient_by_account: LookupMap::new(StorageKey::PatientsByAccount),
+            doctors: IterableMap::new(StorageKey::Doctors),
             no_of_doctors: 0,
-            drugs: Vector::new(b"h"),
+            doctor_by_account: LookupMap::new(StorageKey::DoctorsByAccount),
+            drugs: IterableMap::new(StorageKey::Drugs),
             no_of_drugs: 0,
-            prescriptions: Vector::new(b"p"),
+            prescriptions: IterableMap::new(StorageKey::Prescriptions),
             no_of_prescriptions: 0,
-            appointments: Vector::new(b"e"),
+            appointments: IterableMap::new(StorageKey::Appointments),
             no_of_appointments: 0,
-            notifications: Vector::new(b"k"),
+            notifications: Vector::new(StorageKey::Notifications),
             no_of_notifications: 0,
-            orders: Vector::new(b"o"),
-            messages: Vector::new(b"m"),
+            orders: IterableMap::new(StorageKey::Orders),
+            no_of_orders: 0,
+            messages: Vector::new(StorageKey::Messages),
             appointment_fee: 42_000_000_000,
-            registration_fee:42_000_000_000,
+            registration_fee: 42_000_000_000,
+            registration_fees_collected: 0,
+            appointment_fees_collected: 0,
+            fees_withdrawn: 0,
+            cancellation_policy: CancellationPolicy {
+                full_refund_hours: 24,
+                late_refund_percent: 50,
+            },
+            escrow_held: 0,
+            commission_percent: 10,
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            consents: LookupMap::new(StorageKey::Consents),
+            medical_histories: LookupMap::new(StorageKey::MedicalHistories),
+            medical_records: LookupMap::new(StorageKey::MedicalRecords),
+            no_of_medical_records: 0,
+            encryption_keys: LookupMap::new(StorageKey::EncryptionKeys),
+            encrypted_profiles: LookupMap::new(StorageKey::EncryptedProfiles),
+            audit_log: Vector::new(StorageKey::AuditLog),
+            audit_by_patient: LookupMap::new(StorageKey::AuditByPatient),
+            audit_by_actor: LookupMap::new(StorageKey::AuditByActor),
+            calendars: LookupMap::new(StorageKey::Calendars),
+            booked_slots: LookupMap::new(StorageKey::BookedSlots),
+            reviews: LookupMap::new(StorageKey::Reviews),
+            reviews_by_doctor: LookupMap::new(StorageKey::ReviewsByDoctor),
+            pharmacies: IterableMap::new(StorageKey::Pharmacies),
+            no_of_pharmacies: 0,
+            pharmacy_by_account: LookupMap::new(StorageKey::PharmaciesByAccount),
+            pharmacy_stock: LookupMap::new(StorageKey::PharmacyStock),
+            pharmacy_medicines: LookupMap::new(StorageKey::PharmacyMedicines),
+            stockists: LookupMap::new(StorageKey::Stockists),
+            batches: IterableMap::new(StorageKey::Batches),
+            no_of_batches: 0,
+            stock_batches: LookupMap::new(StorageKey::StockBatches),
+            batch_by_lot: LookupMap::new(StorageKey::BatchesByLot),
+            recalls: Vector::new(StorageKey::Recalls),
+            orders_by_medicine: LookupMap::new(StorageKey::OrdersByMedicine),
+            provenance: LookupMap::new(StorageKey::Provenance),
+            serials: LookupMap::new(StorageKey::Serials),
+            orders_by_patient: LookupMap::new(StorageKey::OrdersByPatient),
+            prescriptions_by_patient: LookupMap::new(StorageKey::PrescriptionsByPatient),
+            appointments_by_patient: LookupMap::new(StorageKey::AppointmentsByPatient),
+            appointments_by_doctor: LookupMap::new(StorageKey::AppointmentsByDoctor),
+            conversations: LookupMap::new(StorageKey::Conversations),
         }
     }
 }
@@ -233,48 +569,22 @@ impl Contract {
     pub fn init(owner: AccountId,users: Option<Vec<AccountId>>) -> Self {
         assert!(!env::state_exists(),"Already initialized");
 
-        Self{
+        let mut contract = Self{
             owner,
-            users: account_vec_to_set(
-                if users.is_some() {
-                    users.unwrap()
-                } else {
-                    vec![]
-                },
-                b"s",
-            ),
-            patients: Vector::new(b"p"),
-            no_of_patients: 0,
-            doctors: Vector::new(b"d"),
-            no_of_doctors: 0,
-            drugs: Vector::new(b"h"),
-            no_of_drugs: 0,
-            prescriptions: Vector::new(b"p"),
-            no_of_prescriptions: 0,
-            appointments: Vector::new(b"e"),
-            no_of_appointments: 0,
-            notifications: Vector::new(b"k"),
-            no_of_notifications: 0,
-            orders: Vector::new(b"o"),
-            messages: Vector::new(b"m"),
-            appointment_fee: 42_000_000_000,
-            registration_fee:42_000_000_000,
+            ..Default::default()
+        };
+        // Accounts passed as `users` start out as admins
+        for account_id in users.unwrap_or_default() {
+            contract.internal_grant_role(&account_id, Role::Admin);
         }
+        contract
     }
 
     pub fn add_notification(&mut self, user_address: AccountId, message: String, ) {
-        let timestamp = env::block_timestamp();
-
-        let notification = Notification {
-            account_id: user_address.clone(),
-            message: message,
-            timestamp: timestamp, 
-        };
-
-        // Store the notification in the vector
-        self.notifications.push(notification);
-
-        env::log_str(&format!("{} {} {}", user_address,"Notification sent to {} at {}", timestamp));
+        self.assert_role(&[Role::Admin, Role::Staff]);
+        let initial_storage = env::storage_usage();
+        self.notify(user_address, message);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
     }
 
 
@@ -302,39 +612,96 @@ impl Contract {
         phone_no: u64,
         image: String,
         description: String,
+        requires_prescription: Option<bool>,
+        controlled_schedule: Option<u8>,
     ) {
-        assert!(
-            self.is_admin(),
-            "Only the  admins can call this method"
-        );
+        self.assert_role(&[Role::Admin, Role::Pharmacist]);
+        require!(discount <= 100, "Discount is a percentage between 0 and 100");
+        let manufactured_at = date_timestamp(&manufacturing_date).expect("Manufacturing date must be YYYY-MM-DD");
+        let expires_at = date_timestamp(&expiry_date).expect("Expiry date must be YYYY-MM-DD");
+        require!(manufactured_at < expires_at, "Medicine must be manufactured before it expires");
         let medicine = Medicine {
             id: self.no_of_drugs,
-            doctor_id: doctor_id,
-            name: name,
-            brand: brand,
-            manufacturer: manufacturer,
-            manufacturing_date: manufacturing_date,
-            expiry_date: expiry_date,
-            company_email: company_email,
-            discount: discount,
-            manufacturer_address: manufacturer_address,
-            price: price,
-            quantity: quantity,
-            current_location: current_location,
-            phone_no: phone_no,
-            image: image,
-            description: description,
+            doctor_id,
+            name,
+            brand,
+            manufacturer,
+            manufacturing_date,
+            expiry_date,
+            company_email,
+            discount,
+            manufacturer_address,
+            price,
+            quantity,
+            current_location,
+            phone_no,
+            image,
+            description,
             availability: true,
+            requires_prescription: requires_prescription.unwrap_or(false),
+            controlled_schedule,
+            manufacturer_account: None,
+            recall_id: None,
         };
 
-        self.drugs.push(medicine);
+        MediEvent::MedicineAdded {
+            medicine_id: medicine.id,
+            name: medicine.name.clone(),
+            price: U128(medicine.price),
+            quantity: U128(medicine.quantity),
+        }
+        .emit();
+        // The initial quantity is one batch dated by the medicine's own dates
+        if quantity > 0 {
+            self.insert_batch(
+                Batch {
+                    id: 0,
+                    medicine_id: medicine.id,
+                    holder: StockHolder::Catalog,
+                    lot_number: batches::OPENING_LOT_NUMBER.to_string(),
+                    manufactured_at,
+                    expires_at,
+                    quantity,
+                    remaining: quantity,
+                    recall_id: None,
+                    custodian: env::predecessor_account_id(),
+                    pending_transfer: None,
+                    serials_registered: 0,
+                },
+                medicine.current_location.clone(),
+            );
+        }
+        self.drugs.insert(medicine.id, medicine);
         self.no_of_drugs += 1;
     }
 
+    // Admin only: relaxing a schedule lets the medicine be sold without a
+    // prescription
+    pub fn update_medicine_schedule(&mut self, medicine_id: u32, requires_prescription: bool, controlled_schedule: Option<u8>) {
+        self.assert_role(&[Role::Admin]);
+        let medicine = self.drugs.get_mut(&medicine_id).expect("Medicine not found");
+        medicine.requires_prescription = requires_prescription;
+        medicine.controlled_schedule = controlled_schedule;
+
+        MediEvent::MedicineScheduleUpdated {
+            medicine_id,
+            requires_prescription,
+            controlled_schedule,
+        }
+        .emit();
+        self.record_audit(None, AuditAction::MedicineScheduleUpdated);
+    }
+
     //========== End of Medicine =======
 
     //========== Doctor =========----
+    #[payable]
     pub fn add_doctor(&mut self,doctor: DoctorInput) {
+        self.assert_self_or_role(&doctor.account_id, &[Role::Admin, Role::Staff]);
+        require!(!self.doctor_by_account.contains_key(&doctor.account_id), "Account already has a doctor profile");
+        let initial_storage = env::storage_usage();
+        let fee_paid = self.collect_fee(self.registration_fee);
+        self.registration_fees_collected += fee_paid;
 
         let doctor = Doctor {
             id: self.no_of_doctors,
@@ -358,93 +725,235 @@ impl Contract {
             appointment_counts: 0,
             successful_treaments: 0,
             is_approved: false,
+            registration_fee_paid: fee_paid,
+            rating_sum: 0,
+            rating_count: 0,
+            earnings: 0,
+            total_earned: 0,
+            total_withdrawn: 0,
         };
 
-        self.doctors.push(doctor);
+        MediEvent::DoctorRegistered {
+            doctor_id: doctor.id,
+            account_id: doctor.account_id.clone(),
+        }
+        .emit();
+        self.doctor_by_account.insert(doctor.account_id.clone(), doctor.id);
+        self.doctors.insert(doctor.id, doctor);
         self.no_of_doctors += 1;
+
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
     }
 
     pub fn approve_doctor(&mut self, id: u32) {
-        assert!(
-            self.is_admin(),
-            "Only the admins can call this method"
-        );
-    
-        let mut doctor:Doctor = self.doctors.get(id).expect("Doctor not found").clone();
-    
+        self.assert_role(&[Role::Admin]);
+
+        let doctor = self.doctors.get_mut(&id).expect("Doctor not found");
+
         doctor.is_approved = true;
-    
-        self.doctors.push(doctor.clone());
-    
-        env::log_str(&format!("Doctor with ID {} has been approved", id));
-    
-        self.add_notification(
-            doctor.account_id.clone(),
-            format!("Your account has been approved. Welcome to the platform!"),
+        let account_id = doctor.account_id.clone();
+
+        MediEvent::DoctorApproved {
+            doctor_id: id,
+            account_id: account_id.clone(),
+        }
+        .emit();
+
+        self.record_audit(None, AuditAction::DoctorApproved);
+        self.internal_grant_role(&account_id, Role::Doctor);
+        self.notify(
+            account_id,
+            "Your account has been approved. Welcome to the platform!".to_string(),
         );
     }
-    
 
-    //Update by the doctor
+
+    //Update by a doctor the patient has granted write access. Adds a Note,
+    //see `add_medical_record` for the other kinds.
     pub fn update_patient_medical(&mut self,id: u32,new_medical_history: String) {
-        let caller_id = env::predecessor_account_id();
-        assert!(self.is_doctor(caller_id), "Only the assigned doctor can update the medical history.");
+        let initial_storage = env::storage_usage();
+        self.internal_add_medical_record(id, RecordKind::Note, new_medical_history, None, vec![], None, None);
+        self.record_audit(Some(id), AuditAction::MedicalHistoryUpdated);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
 
-        let mut patient: Patient = self.patients.get(id).expect("Doctor not found").clone();
+    //========== Appointment lifecycle =========
+    pub fn confirm_appointment(&mut self, id: u32) {
+        let appointment = self.transition_appointment(id, AppointmentStatus::Confirmed, &[AppointmentParty::Doctor]);
 
-        patient.medical_history.push(new_medical_history);
+        let patient_account = self.patients.get(&appointment.patient_id).expect("Patient not found").account_id.clone();
+        self.notify(patient_account, format!("Your appointment {} has been confirmed", id));
+    }
 
-        self.patients.push(patient.clone());
+    pub fn check_in_appointment(&mut self, id: u32) {
+        self.transition_appointment(
+            id,
+            AppointmentStatus::CheckedIn,
+            &[AppointmentParty::Patient, AppointmentParty::Doctor],
+        );
+    }
 
-        env::log_str(
-            format!(
-                "Patient medical history updated by doctor or admin for patient_id: {}",
-                id
-            )
-            .as_str(),
+    pub fn complete_appointment(&mut self, id: u32) {
+        let appointment = self.transition_appointment(id, AppointmentStatus::Completed, &[AppointmentParty::Doctor]);
+        self.doctors.get_mut(&appointment.doctor_id).expect("Doctor not found").appointment_counts += 1;
+        self.release_escrow(id);
+    }
+
+    pub fn mark_no_show(&mut self, id: u32) {
+        let appointment = self.transition_appointment(id, AppointmentStatus::NoShow, &[AppointmentParty::Doctor]);
+        require!(
+            env::block_timestamp() >= appointment.slot.starts_at(),
+            "A no-show can only be recorded once the slot has started"
         );
+        self.release_escrow(id);
     }
 
-    pub fn complete_appointment(&mut self, id: u32, patient_id: u32)  {
-        // Retrieve the appointment from storage
-        let mut appointment: Appointment  = self.appointments.get(id)
-            .expect("Appointment not found").clone();
+    // A patient can still cancel a started appointment the doctor let lapse,
+    // see `appointment_lapsed`, and is then refunded in full
+    pub fn cancel_appointment(&mut self, id: u32) {
+        let status = self.appointments.get(&id).expect("Appointment not found").status;
+        let appointment = self.transition_appointment(
+            id,
+            AppointmentStatus::Cancelled,
+            &[AppointmentParty::Patient, AppointmentParty::Doctor],
+        );
+        let lapsed = self.appointment_lapsed(&appointment, status);
+        // Otherwise, once the slot has started a patient who did not come is
+        // a no-show
+        require!(
+            self.caller_is_appointment_doctor(&appointment) || lapsed || env::block_timestamp() < appointment.slot.starts_at(),
+            "Appointments cannot be cancelled by the patient once they have started"
+        );
+        self.release_slot(appointment.doctor_id, appointment.slot, id);
+        let refund = if lapsed { appointment.escrow } else { self.cancellation_refund(&appointment) };
+        if refund > 0 {
+            self.refund_appointment(id, refund);
+        }
+        // Whatever a late cancellation leaves in escrow goes to the doctor
+        self.release_escrow(id);
 
-        // Verify the appointment belongs to the specified patient
-        assert_eq!(appointment.patient_id, patient_id, "Appointment does not belong to the specified patient");
+        let other_party = self.other_appointment_party(&appointment);
+        self.notify(other_party, format!("Appointment {} has been cancelled", id));
+    }
 
-        // Check if the appointment is already completed
-        assert!(appointment.status == AppointmentStatus::Completed, "Appointment is already completed");
+    // Closes the appointment as Rescheduled and books a new Requested one for
+    // the same patient and doctor. Returns the id of the new appointment.
+    pub fn reschedule_appointment(&mut self, id: u32, slot: Slot) -> u32 {
+        let previous = self.transition_appointment(
+            id,
+            AppointmentStatus::Rescheduled,
+            &[AppointmentParty::Patient, AppointmentParty::Doctor],
+        );
+        require!(
+            self.caller_is_appointment_doctor(&previous) || env::block_timestamp() < self.full_refund_cutoff(&previous),
+            format!(
+                "Appointments can only be rescheduled at least {} hours ahead",
+                self.cancellation_policy.full_refund_hours
+            )
+        );
+
+        let new_id = self.no_of_appointments;
+        self.release_slot(previous.doctor_id, previous.slot, id);
+        self.reserve_slot(previous.doctor_id, slot, new_id);
+        let appointment = Appointment {
+            id: new_id,
+            patient_id: previous.patient_id,
+            doctor_id: previous.doctor_id,
+            slot,
+            condition: previous.condition.clone(),
+            status: AppointmentStatus::Requested,
+            message: previous.message.clone(),
+            history: vec![StatusChange {
+                status: AppointmentStatus::Requested,
+                actor: env::predecessor_account_id(),
+                timestamp: env::block_timestamp(),
+            }],
+            rescheduled_from: Some(id),
+            rescheduled_to: None,
+            // The fee already paid covers the new booking
+            fee_paid: previous.fee_paid,
+            fee_refunded: 0,
+            escrow: previous.escrow,
+        };
+        appointment.emit_status_changed();
+        self.insert_appointment(appointment);
+        let closed = self.appointments.get_mut(&id).unwrap();
+        closed.rescheduled_to = Some(new_id);
+        closed.fee_paid = 0;
+        closed.escrow = 0;
+
+        let other_party = self.other_appointment_party(&previous);
+        self.notify(other_party, format!("Appointment {} has been rescheduled as {}", id, new_id));
 
-        appointment.status = AppointmentStatus::Completed;
+        new_id
     }
 
-    pub fn prescribe_medicine(&mut self,id:u32, medicine_id: u32, patient_id: u32,doctor_id:u32) {
-        let caller_id = env::predecessor_account_id();
-        assert!(self.is_doctor(caller_id), "Only doctors can prescribe medicine.");
+    // Written by the calling doctor; returns the new prescription id
+    pub fn prescribe_medicine(&mut self, prescription: PrescriptionInput) -> u32 {
+        let doctor_id = self.caller_doctor_id();
+        let (medicine_id, patient_id) = (prescription.medicine_id, prescription.patient_id);
+        require!(self.patients.contains_key(&patient_id), "Patient not found");
+        require!(self.drugs.contains_key(&medicine_id), "Medicine not found");
+        require!(prescription.quantity > 0, "Quantity must be greater than 0.");
+        require!(prescription.expires_at > env::block_timestamp(), "Prescription expiry must be in the future");
+        if let Some(appointment_id) = prescription.appointment_id {
+            let appointment = self.appointments.get(&appointment_id).expect("Appointment not found");
+            require!(
+                appointment.patient_id == patient_id && appointment.doctor_id == doctor_id,
+                "Appointment does not belong to this patient and doctor"
+            );
+        }
 
+        let id = self.no_of_prescriptions;
         let prescription = Prescription {
-            id: id,
-            medicine_id:medicine_id,
-            patient_id: patient_id,
-            doctor_id: doctor_id,
+            id,
+            medicine_id,
+            patient_id,
+            doctor_id,
             date: env::block_timestamp(),
+            dosage: prescription.dosage,
+            frequency: prescription.frequency,
+            duration_days: prescription.duration_days,
+            quantity: prescription.quantity,
+            refills: prescription.refills,
+            expires_at: prescription.expires_at,
+            fills_used: 0,
+            instructions: prescription.instructions,
+            appointment_id: prescription.appointment_id,
         };
 
-        self.prescriptions.push(prescription);
+        MediEvent::PrescriptionCreated {
+            prescription_id: id,
+            patient_id,
+            doctor_id,
+            medicine_id,
+        }
+        .emit();
+        self.prescriptions.insert(id, prescription);
+        self.prescriptions_by_patient.entry(patient_id).or_default().push(id);
         self.no_of_prescriptions += 1;
+        self.record_audit(Some(patient_id), AuditAction::PrescriptionCreated);
+
+        self.notify(env::predecessor_account_id(), "You have successfully added medicine.".to_string());
+        let patient_account = self.patients[&patient_id].account_id.clone();
+        self.notify(patient_account, format!("You have a new prescription {}", id));
 
-        self.add_notification(env::predecessor_account_id(), "You have successfully added medicine.".to_string());
+        id
     }
 
     fn is_doctor(&self, account_id: AccountId) -> bool {
-        // Implement logic to verify if the account_id belongs to a doctor
-        self.doctors.iter().any(|doctor| doctor.account_id == account_id)
+        self.has_role_internal(&account_id, Role::Doctor)
     }
 
     //======== End Of Doctor
     //===========  Patient
+    #[payable]
     pub fn add_patient(&mut self,patient: PatientInput) {
+        self.assert_self_or_role(&patient.account_id, &[Role::Admin, Role::Staff]);
+        require!(!self.patient_by_account.contains_key(&patient.account_id), "Account already has a patient profile");
+        let initial_storage = env::storage_usage();
+        let fee_paid = self.collect_fee(self.registration_fee);
+        self.registration_fees_collected += fee_paid;
 
         let patient = Patient {
             id: self.no_of_patients,
@@ -462,207 +971,558 @@ impl Contract {
             profile_pic: patient.profile_pic,
             account_id: patient.account_id,
             message: patient.message,
-            medical_history: vec![],
             bought_medicine: vec![],
+            registration_fee_paid: fee_paid,
         };
 
-        self.patients.push(patient);
-        self.no_of_doctors += 1;
+        self.internal_grant_role(&patient.account_id, Role::Patient);
+        self.patient_by_account.insert(patient.account_id.clone(), patient.id);
+        MediEvent::PatientRegistered {
+            patient_id: patient.id,
+            account_id: patient.account_id.clone(),
+        }
+        .emit();
+        self.patients.insert(patient.id, patient);
+        self.no_of_patients += 1;
 
-        log!("Patient was registered successfully!");
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
     }
 
-    pub fn book_appointment(&mut self,id:u32,patient_id: u32, doctor_id: u32, from: String, to: String, appointment_date: String, condition: String, message: String) {
+    // `slot` must be one of `get_available_slots`. With `share_history` the
+    // doctor gets a time-limited grant to the patient's medical history, see
+    // `APPOINTMENT_CONSENT_DURATION`.
+    #[payable]
+    pub fn book_appointment(&mut self, patient_id: u32, doctor_id: u32, slot: Slot, condition: String, message: String, share_history: Option<bool>) -> u32 {
+        let caller_id = env::predecessor_account_id();
+        let patient = self.patients.get(&patient_id).expect("Patient not found");
+        require!(patient.account_id == caller_id, "Only the patient can book their appointment");
+        let doctor = self.doctors.get(&doctor_id).expect("Doctor not found");
+        require!(doctor.is_approved, "Doctor is not approved");
+        let doctor_account = doctor.account_id.clone();
+        let initial_storage = env::storage_usage();
 
+        let fee_paid = self.collect_fee(self.appointment_fee);
+        self.escrow_held += fee_paid;
+
+        self.reserve_slot(doctor_id, slot, self.no_of_appointments);
         let appointment = Appointment {
-            id: self.no_of_notifications,
-            patient_id: patient_id,
-            doctor_id: doctor_id,
-            from: from,
-            to: to,
-            appointment_date: appointment_date,
-            condition: condition,
-            status: AppointmentStatus::Pending,
-            message: message,
-            is_open: true,
+            id: self.no_of_appointments,
+            patient_id,
+            doctor_id,
+            slot,
+            condition,
+            status: AppointmentStatus::Requested,
+            message,
+            history: vec![StatusChange {
+                status: AppointmentStatus::Requested,
+                actor: caller_id.clone(),
+                timestamp: env::block_timestamp(),
+            }],
+            rescheduled_from: None,
+            rescheduled_to: None,
+            fee_paid,
+            fee_refunded: 0,
+            escrow: fee_paid,
         };
 
-        self.appointments.push(appointment);
-        self.no_of_appointments += 1;
+        let id = appointment.id;
+        appointment.emit_status_changed();
+        self.insert_appointment(appointment);
+        if share_history.unwrap_or(false) {
+            self.grant_appointment_consent(patient_id, doctor_id);
+        }
 
-        
-        self.add_notification(env::predecessor_account_id(), "You have successfully booked an appointment".to_string());
+        self.notify(caller_id.clone(), "You have successfully booked an appointment".to_string());
+        self.notify(doctor_account, format!("New appointment request {}", id));
+        self.charge_storage(&caller_id, initial_storage);
 
+        id
     }
 
+    // Reserves the stock, records a Pending order and forwards the payment to
+    // the seller: the pharmacy when `pharmacy_id` is set, otherwise the owner
+    // selling from the catalog stock. Any deposit above the discounted total
+    // is refunded. Prescription-only medicines consume a fill of
+    // `prescription_id`. Packs keep verifying as genuine until the seller
+    // records the ones handed over with `dispense_serials`.
     #[payable]
-    pub fn buy_medicine(&mut self, medicine_id: u32, quantity: u128, patient_id: u32) -> Promise {
-        // Retrieve the medicine details
-        let medicine = self.get_medicine_by_id(medicine_id).expect("Medicine not found");
-    
+    pub fn buy_medicine(
+        &mut self,
+        medicine_id: u32,
+        quantity: u128,
+        patient_id: u32,
+        prescription_id: Option<u32>,
+        pharmacy_id: Option<u32>,
+    ) -> Promise {
+        let caller_id = env::predecessor_account_id();
+        let patient = self.patients.get(&patient_id).expect("Patient not found");
+        require!(patient.account_id == caller_id, "Only the patient can buy medicine for themselves");
+
         // Check if the quantity is valid (should be greater than 0)
         assert!(quantity > 0, "Quantity must be greater than 0.");
-    
-        // Calculate the total price
-        let total_price = medicine.price * quantity as u128;
-    
-        Promise::new(self.owner.clone()).transfer(NearToken::from_yoctonear(total_price.try_into().unwrap()))
+        let medicine = self.drugs.get(&medicine_id).expect("Medicine not found");
+        require!(medicine.recall_id.is_none(), "Medicine has been recalled");
+        let prescription_id = self.fill_prescription(prescription_id, patient_id, medicine_id, quantity);
+
+        // Reserve the stock until the payment is settled
+        let (price, discount, seller) = match pharmacy_id {
+            Some(pharmacy_id) => {
+                let (price, discount) = self.take_pharmacy_stock(pharmacy_id, medicine_id, quantity);
+                (price, discount, self.pharmacies[&pharmacy_id].account_id.clone())
+            }
+            None => {
+                let medicine = self.drugs.get_mut(&medicine_id).expect("Medicine not found");
+                require!(medicine.availability, "Medicine is not available");
+                require!(medicine.quantity >= quantity, "Not enough stock");
+                medicine.quantity -= quantity;
+                if medicine.quantity == 0 {
+                    medicine.availability = false;
+                }
+                medicine.emit_stock_changed();
+                (medicine.price, medicine.discount, self.owner.clone())
+            }
+        };
+        let holder = pharmacy_id.map_or(StockHolder::Catalog, StockHolder::Pharmacy);
+        let batches = self.dispense_batches(holder, medicine_id, quantity);
+
+        // Calculate the total price after discount
+        let total_price = price
+            .checked_mul(quantity)
+            .and_then(|gross| gross.checked_mul(100 - discount))
+            .expect("Total price overflow")
+            / 100;
+        let deposit = env::attached_deposit().as_yoctonear();
+        require!(deposit >= total_price, format!("Attached deposit must cover the total price of {}", total_price));
+
+        let order = Order {
+            id: self.no_of_orders,
+            medicine_id,
+            patient_id,
+            price,
+            discount,
+            quantity,
+            payment_amount: total_price,
+            status: OrderStatus::Pending,
+            date: env::block_timestamp(),
+            prescription_id,
+            pharmacy_id,
+            batches,
+            serials: vec![],
+        };
+        let order_id = order.id;
+        MediEvent::OrderPlaced {
+            order_id,
+            patient_id,
+            medicine_id,
+            quantity: U128(quantity),
+            payment_amount: U128(total_price),
+        }
+        .emit();
+        self.orders.insert(order_id, order);
+        self.orders_by_medicine.entry(medicine_id).or_default().push(order_id);
+        self.orders_by_patient.entry(patient_id).or_default().push(order_id);
+        self.no_of_orders += 1;
+
+        self.refund_excess(&caller_id, deposit, total_price);
+
+        Promise::new(seller)
+            .transfer(NearToken::from_yoctonear(total_price))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .on_order_payment(order_id),
+            )
     }
-    
+
+    // Settles an order once the payment transfer has resolved
+    #[private]
+    pub fn on_order_payment(
+        &mut self,
+        order_id: u32,
+        #[callback_result] payment: Result<(), PromiseError>,
+    ) -> Order {
+        let order = self.orders.get_mut(&order_id).expect("Order not found");
+        require!(order.status == OrderStatus::Pending, "Order is already settled");
+
+        if payment.is_ok() {
+            order.status = OrderStatus::Paid;
+            let (patient_id, medicine_id) = (order.patient_id, order.medicine_id);
+            let patient = self.patients.get_mut(&patient_id).expect("Patient not found");
+            if !patient.bought_medicine.contains(&medicine_id) {
+                patient.bought_medicine.push(medicine_id);
+            }
+        } else {
+            order.status = OrderStatus::Failed;
+            let order = order.clone();
+            self.restock_batches(&order.batches);
+            self.restock_serials(&order.serials);
+            match order.pharmacy_id {
+                Some(pharmacy_id) => self.add_pharmacy_stock(pharmacy_id, order.medicine_id, order.quantity),
+                None => {
+                    let medicine = self.drugs.get_mut(&order.medicine_id).expect("Medicine not found");
+                    medicine.quantity += order.quantity;
+                    medicine.availability = true;
+                    medicine.emit_stock_changed();
+                }
+            }
+            if let Some(prescription) = order.prescription_id.and_then(|id| self.prescriptions.get_mut(&id)) {
+                prescription.fills_used -= 1;
+            }
+            let patient = self.patients.get(&order.patient_id).expect("Patient not found");
+            Promise::new(patient.account_id.clone()).transfer(NearToken::from_yoctonear(order.payment_amount));
+        }
+
+        let order = self.orders.get(&order_id).unwrap().clone();
+        MediEvent::OrderStatusChanged {
+            order_id,
+            status: order.status,
+        }
+        .emit();
+        order
+    }
+
 
     // End of patient
     // Admin
 
     //Update by Admin only
      pub fn update_registration_fee(&mut self, new_fee: u128) {
-        assert!(
-            self.is_admin(),
-            "Only the owner(patient) and admins can call this method"
-        );
+        self.assert_role(&[Role::Admin]);
         self.registration_fee = new_fee;
-        env::log_str(&format!("Registration fee updated to {}", new_fee));
+        self.record_audit(None, AuditAction::FeeUpdated);
+        MediEvent::FeeUpdated {
+            fee: FeeKind::Registration,
+            amount: U128(new_fee),
+        }
+        .emit();
     }
 
     // Function to update the appointment fee
     pub fn update_appointment_fee(&mut self, new_fee: u128) {
-        assert!(
-            self.is_admin(),
-            "Only the owner(patient) and admins can call this method"
-        );
+        self.assert_role(&[Role::Admin]);
         self.appointment_fee = new_fee;
-        env::log_str(&format!("Appointment fee updated to {}", new_fee));
+        self.record_audit(None, AuditAction::FeeUpdated);
+        MediEvent::FeeUpdated {
+            fee: FeeKind::Appointment,
+            amount: U128(new_fee),
+        }
+        .emit();
     }
 
 
-    // Function to update the admin address
+    // Function to update the admin address, i.e. transfer the Owner role
     pub fn update_admin_address(&mut self, new_admin: AccountId) {
-        assert!(
-            self.is_admin(),
-            "Only the owner(patient) and admins can call this method"
-        );
-        self.owner = new_admin.clone();
-        env::log_str(&format!("Admin address updated to {}", new_admin));
+        self.assert_owner();
+        let old_owner = std::mem::replace(&mut self.owner, new_admin.clone());
+        self.record_audit(None, AuditAction::OwnerChanged);
+        MediEvent::OwnerChanged {
+            old_owner,
+            new_owner: new_admin,
+        }
+        .emit();
+    }
+
+    pub fn update_cancellation_policy(&mut self, policy: CancellationPolicy) {
+        self.assert_role(&[Role::Admin]);
+        require!(policy.late_refund_percent <= 100, "Refund is a percentage between 0 and 100");
+        MediEvent::CancellationPolicyUpdated {
+            full_refund_hours: policy.full_refund_hours,
+            late_refund_percent: policy.late_refund_percent,
+        }
+        .emit();
+        self.cancellation_policy = policy;
+        self.record_audit(None, AuditAction::CancellationPolicyUpdated);
+    }
+
+    pub fn get_cancellation_policy(&self) -> CancellationPolicy {
+        self.cancellation_policy.clone()
+    }
+
+    pub fn get_fees_collected(&self) -> FeeTreasury {
+        let total = self.registration_fees_collected + self.appointment_fees_collected;
+        FeeTreasury {
+            registration_fees: self.registration_fees_collected,
+            appointment_fees: self.appointment_fees_collected,
+            total,
+            escrowed: self.escrow_held,
+            withdrawn: self.fees_withdrawn,
+            withdrawable: total - self.fees_withdrawn,
+        }
+    }
+
+    // Owner only. Sends collected fees not yet withdrawn to the owner, all of
+    // them unless `amount` is set. Escrowed fees and storage deposits stay.
+    pub fn withdraw_fees(&mut self, amount: Option<u128>) -> Promise {
+        self.assert_owner();
+        let withdrawable = self.registration_fees_collected + self.appointment_fees_collected - self.fees_withdrawn;
+        let amount = amount.unwrap_or(withdrawable);
+        require!(amount > 0, "Nothing to withdraw");
+        require!(amount <= withdrawable, "Amount exceeds the withdrawable fees");
+
+        self.fees_withdrawn += amount;
+
+        Promise::new(self.owner.clone())
+            .transfer(NearToken::from_yoctonear(amount))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .on_fees_withdrawn(self.owner.clone(), amount),
+            )
+    }
+
+    // Makes the fees withdrawable again if the transfer did not go through
+    #[private]
+    pub fn on_fees_withdrawn(
+        &mut self,
+        owner: AccountId,
+        amount: u128,
+        #[callback_result] transfer: Result<(), PromiseError>,
+    ) -> bool {
+        if transfer.is_err() {
+            self.fees_withdrawn -= amount;
+            return false;
+        }
+
+        MediEvent::FeesWithdrawn {
+            owner,
+            amount: U128(amount),
+        }
+        .emit();
+        true
+    }
+
+    pub fn get_registration_fee(&self) -> u128 {
+        self.registration_fee
+    }
+
+    pub fn get_appointment_fee(&self) -> u128 {
+        self.appointment_fee
     }
 
     //======== End Of Admin
     //=========  Get patient data
-    pub fn get_all_patient_orders(&self) -> Vec<Order> {
-        self.orders
-            .iter()  // Iterate over references to `Order`
-            .cloned() // Clone each `Order` to get owned values
-            .collect() // Collect into a Vec<Order>
+    pub fn get_all_patient_orders(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Order> {
+        paginate(self.orders.values(), from_index, limit)
+    }
+
+    pub fn get_orders_count(&self) -> u32 {
+        self.orders.len()
     }
-    
+
+    pub fn get_patient_orders(&self, patient_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<Order> {
+        paginate(
+            self.orders_by_patient
+                .get(&patient_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.orders.get(id)),
+            from_index,
+            limit,
+        )
+    }
+
+    pub fn get_order(&self, order_id: u32) -> Option<Order> {
+        self.orders.get(&order_id).cloned()
+    }
+
 
     // Retrieve all prescription details
-    pub fn get_all_prescription_details(&self) -> Vec<Prescription> {
-        self.prescriptions
-            .iter()  // Iterate over references to `Prescription`
-            .cloned() // Clone the actual `Prescription`, not just the reference
-            .collect() // Collect into a Vec<Prescription>
+    pub fn get_all_prescription_details(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Prescription> {
+        paginate(self.prescriptions.values(), from_index, limit)
+    }
+
+    pub fn get_prescriptions_count(&self) -> u32 {
+        self.prescriptions.len()
+    }
+
+    pub fn get_prescription(&self, prescription_id: u32) -> Option<Prescription> {
+        self.prescriptions.get(&prescription_id).cloned()
     }
-    
 
-    pub fn get_all_registered_patients(&self) -> Vec<Patient> {
-        self.patients
-            .iter()     // Iterate over the vector of patients
-            .cloned()   // Clone the Patient objects to return owned values
-            .collect()  // Collect into a Vec<Patient>
+    pub fn get_patient_prescriptions(&self, patient_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<Prescription> {
+        paginate(
+            self.prescriptions_by_patient
+                .get(&patient_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.prescriptions.get(id)),
+            from_index,
+            limit,
+        )
     }
-    
+
+
+    pub fn get_all_registered_patients(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Patient> {
+        paginate(self.patients.values(), from_index, limit)
+    }
+
+    pub fn get_patients_count(&self) -> u32 {
+        self.patients.len()
+    }
+
     pub fn get_patient_id(&self, patient_id: u32) -> Option<Patient> {
-        // Assuming you have a patients collection to look up the patient by ID
-        self.patients.iter().find(|patient| patient.id == patient_id).cloned()
-    }
-
-    pub fn get_patient_appointment(&self, patient_id: u32) -> Vec<Appointment> {
-        let appointments: Vec<Appointment> = self
-            .appointments
-            .iter() // Iterate over the appointments
-            .filter(|appointment| appointment.patient_id == patient_id) // Filter by patient ID
-            .cloned() // Clone the appointment to return ownership
-            .collect(); // Collect the results into a Vec<Appointment>
-    
-        appointments
-    }
-    
-
-    pub fn get_patient_medical_history(&self, patient_id: u32) -> Vec<String> {
-        // Retrieve the patient by ID
-        let patient = self.get_patient_id(patient_id).unwrap(); // Assuming you have this method
-    
-        // Return the patient's medical history (Vec<String>)
-        patient.medical_history.clone() // Clone to return ownership
-    }
-    
-    pub fn get_patient_appointment_history(&self, patient_id: u32) -> Vec<Appointment> {
-        self.appointments.iter()
-            .filter(|appointment| appointment.patient_id == patient_id)
+        self.patients.get(&patient_id).cloned()
+    }
+
+    // Resolves the patient profile of a signed-in account
+    pub fn get_patient_by_account(&self, account_id: AccountId) -> Option<Patient> {
+        self.patient_by_account
+            .get(&account_id)
+            .and_then(|id| self.patients.get(id))
             .cloned()
-            .collect()
+    }
+
+    // Appointments that are still in progress for the patient
+    pub fn get_patient_appointment(&self, patient_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<Appointment> {
+        paginate(
+            self.appointments_by_patient
+                .get(&patient_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.appointments.get(id))
+                .filter(|appointment| !appointment.status.is_closed()),
+            from_index,
+            limit,
+        )
+    }
+
+
+    // A change method because views cannot tell who is asking; readable by
+    // the patient and by doctors holding read consent. Every read is audited,
+    // see `record_read_audit` for who pays for the entry.
+    // Lists current entries, optionally of one `kind` and within an inclusive
+    // block timestamp range; amended entries are left out.
+    pub fn get_patient_medical_history(
+        &mut self,
+        patient_id: u32,
+        kind: Option<RecordKind>,
+        from_timestamp: Option<u64>,
+        to_timestamp: Option<u64>,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<MedicalRecordEntry> {
+        self.assert_medical_access(patient_id, Access::Read);
+        self.record_read_audit(patient_id);
+
+        paginate(
+            self.medical_histories
+                .get(&patient_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.medical_records.get(id))
+                .filter(|entry| {
+                    entry.superseded_by.is_none()
+                        && kind.map_or(true, |kind| entry.kind == kind)
+                        && from_timestamp.map_or(true, |from| entry.timestamp >= from)
+                        && to_timestamp.map_or(true, |to| entry.timestamp <= to)
+                }),
+            from_index,
+            limit,
+        )
+    }
+
+    pub fn get_patient_appointment_history(&self, patient_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<Appointment> {
+        paginate(
+            self.appointments_by_patient
+                .get(&patient_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.appointments.get(id))
+                .filter(|appointment| appointment.status.is_closed()),
+            from_index,
+            limit,
+        )
     }
 
     pub fn get_bought_medicine_by_patient(&self, patient_id: u32) -> Vec<Medicine> {
-        let patient = self.get_patient_id(patient_id); // Assuming you have this method
+        let patient = self.patients.get(&patient_id).expect("Patient not found");
 
         // Collect medicines based on the IDs stored in `bought_medicine`
-        patient.unwrap().bought_medicine.iter()
-            .filter_map(|&medicine_id| self.get_medicine_by_id(medicine_id as u32)) // Assuming this method retrieves Medicine by ID
+        patient.bought_medicine.iter()
+            .filter_map(|&medicine_id| self.get_medicine_by_id(medicine_id))
             .collect()
     }
 
-    pub fn get_all_appointments(&self) -> Vec<Appointment>{
-        self.appointments.iter().map(|appointment| appointment.clone()).collect()
+    pub fn get_all_appointments(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Appointment>{
+        paginate(self.appointments.values(), from_index, limit)
+    }
+
+    pub fn get_appointments_count(&self) -> u32 {
+        self.appointments.len()
     }
 
     // Get doctors data
-    pub fn get_all_doctors_data(&self) -> Vec<Doctor>{
-        self.doctors.iter().map(|doctor | doctor.clone()).collect()
+    pub fn get_all_doctors_data(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Doctor>{
+        paginate(self.doctors.values(), from_index, limit)
+    }
+
+    pub fn get_doctors_count(&self) -> u32 {
+        self.doctors.len()
     }
 
-    pub fn get_approved_doctors(&self) -> Vec<Doctor> {
-        self.doctors.iter().filter(|doctor| doctor.is_approved).cloned().collect()
+    pub fn get_approved_doctors(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Doctor> {
+        paginate(self.doctors.values().filter(|doctor| doctor.is_approved), from_index, limit)
     }
 
 
 
     pub fn get_doctor_details(&self, doctor_id: u32) -> Option<Doctor> {
-        for doctor in self.doctors.iter() {
-            if doctor.id == doctor_id {
-                return Some(doctor.clone());
-            }
-        }
-        None
+        self.doctors.get(&doctor_id).cloned()
     }
 
 
-    pub fn get_doctor_appointment_historys(&self, doctor_id: u32) -> Vec<Appointment>{
-        let mut history: Vec<Appointment> = Vec::new(); // Initialize an empty vector for the appointment history
+    // Resolves the doctor profile of a signed-in account
+    pub fn get_doctor_by_account(&self, account_id: AccountId) -> Option<Doctor> {
+        self.doctor_by_account
+            .get(&account_id)
+            .and_then(|id| self.doctors.get(id))
+            .cloned()
+    }
 
-        for i in 0..self.appointments.len() {
-            if let Some(appointment) = self.appointments.get(i) {
-                if appointment.doctor_id == doctor_id && !appointment.is_open {
-                    history.push(appointment.clone()); // Add the closed appointment to the history
-                }
-            }
-        }
-    
-        history 
+    // Appointments that are still in progress for the doctor
+    pub fn get_doctor_appointments(&self, doctor_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<Appointment> {
+        paginate(
+            self.appointments_by_doctor
+                .get(&doctor_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.appointments.get(id))
+                .filter(|appointment| !appointment.status.is_closed()),
+            from_index,
+            limit,
+        )
+    }
+
+    pub fn get_doctor_appointment_historys(&self, doctor_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<Appointment>{
+        paginate(
+            self.appointments_by_doctor
+                .get(&doctor_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.appointments.get(id))
+                .filter(|appointment| appointment.status.is_closed()),
+            from_index,
+            limit,
+        )
     }
 
     // Get doctor medicine
-    pub fn get_all_registered_medicines(&self) -> Vec<Medicine>{
-        return self.drugs.iter().cloned().collect()
+    pub fn get_all_registered_medicines(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Medicine>{
+        paginate(self.drugs.values(), from_index, limit)
+    }
+
+    pub fn get_medicines_count(&self) -> u32 {
+        self.drugs.len()
     }
 
     pub fn get_medicine_by_id(&self, medicine_id: u32) -> Option<Medicine> {
-        // Assuming `self.medicines` is a collection (e.g., a vector or map) of medicines
-        self.drugs.iter().find(|&medicine| medicine.id == medicine_id).cloned()
+        self.drugs.get(&medicine_id).cloned()
     }
 
     pub fn send_message(&mut self, recipient: AccountId, message: String) -> Message {
         let sender = env::predecessor_account_id();
         let timestamp = env::block_timestamp();
+        let initial_storage = env::storage_usage();
 
         let new_message = Message {
             patient_id: if self.is_doctor(sender.clone()) { recipient.clone() } else { sender.clone() },
@@ -671,23 +1531,32 @@ impl Contract {
             message,
         };
 
+        self.conversations
+            .entry((new_message.patient_id.clone(), new_message.doctor_id.clone()))
+            .or_default()
+            .push(self.messages.len());
         self.messages.push(new_message.clone());
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
         new_message
     }
 
-    pub fn get_messages(&self, patient: AccountId, doctor: AccountId) -> Vec<Message> {
-        self.messages
-            .iter()
-            .filter(|msg| msg.patient_id == patient && msg.doctor_id == doctor)
-            .cloned()
-            .collect()
+    pub fn get_messages(&self, patient: AccountId, doctor: AccountId, from_index: Option<u32>, limit: Option<u32>) -> Vec<Message> {
+        paginate(
+            self.conversations
+                .get(&(patient, doctor))
+                .into_iter()
+                .flatten()
+                .filter_map(|index| self.messages.get(*index)),
+            from_index,
+            limit,
+        )
     }
 
-   
+
 }
 
 
 // Tests in a separated file (see more here -> http://xion.io/post/code/rust-unit-test-placement.html)
-// #[cfg(test)]
-// #[path = "./tests.rs"]
-// mod tests;
\ No newline at end of file
+#[cfg(test)]
+#[path = "./tests.rs"]
+mod tests;
@@ -0,0 +1,553 @@
+use crate::*;
+use near_sdk::borsh::BorshDeserialize;
+use std::collections::HashMap;
+
+// Patient id given to legacy appointments, prescriptions and orders. The first
+// deployment stored every patient with id 0, so their patient cannot be told.
+pub const UNATTRIBUTED_PATIENT: u32 = u32::MAX;
+
+// Record layouts as they were stored by the first testnet deployment. They are
+// frozen here so the migration keeps reading the legacy state correctly while
+// the live types keep evolving.
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyPatient {
+    pub(crate) id: u32,
+    pub(crate) title: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) gender: String,
+    pub(crate) condition: String,
+    pub(crate) phone: String,
+    pub(crate) email: String,
+    pub(crate) dob: String,
+    pub(crate) city: String,
+    pub(crate) address: String,
+    pub(crate) doctor: String,
+    pub(crate) profile_pic: String,
+    pub(crate) account_id: AccountId,
+    pub(crate) message: String,
+    pub(crate) medical_history: Vec<String>,
+    pub(crate) bought_medicine: Vec<i32>,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyDoctor {
+    pub(crate) id: u32,
+    pub(crate) title: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) gender: String,
+    pub(crate) designation: String,
+    pub(crate) last_work: String,
+    pub(crate) email: String,
+    pub(crate) college_name: String,
+    pub(crate) college_id: String,
+    pub(crate) joining_year: String,
+    pub(crate) end_year: String,
+    pub(crate) specialization: String,
+    pub(crate) registration_id: String,
+    pub(crate) college_address: String,
+    pub(crate) account_id: AccountId,
+    pub(crate) profile_pic: String,
+    pub(crate) bio: String,
+    pub(crate) appointment_counts: i32,
+    pub(crate) successful_treaments: i32,
+    pub(crate) is_approved: bool,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyMedicine {
+    pub(crate) id: u32,
+    pub(crate) doctor_id: u32,
+    pub(crate) name: String,
+    pub(crate) brand: String,
+    pub(crate) manufacturer: String,
+    pub(crate) manufacturing_date: String,
+    pub(crate) expiry_date: String,
+    pub(crate) company_email: String,
+    pub(crate) discount: u128,
+    pub(crate) manufacturer_address: String,
+    pub(crate) price: u128,
+    pub(crate) quantity: u128,
+    pub(crate) current_location: String,
+    pub(crate) phone_no: u64,
+    pub(crate) image: String,
+    pub(crate) description: String,
+    pub(crate) availability: bool,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyPrescription {
+    pub(crate) id: u32,
+    pub(crate) medicine_id: u32,
+    pub(crate) patient_id: u32,
+    pub(crate) doctor_id: u32,
+    pub(crate) date: u64,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) enum LegacyAppointmentStatus {
+    Pending,
+    Completed,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyAppointment {
+    pub(crate) id: u32,
+    pub(crate) patient_id: u32,
+    pub(crate) doctor_id: u32,
+    pub(crate) from: String,
+    pub(crate) to: String,
+    pub(crate) appointment_date: String,
+    pub(crate) condition: String,
+    pub(crate) status: LegacyAppointmentStatus,
+    pub(crate) message: String,
+    pub(crate) is_open: bool,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyMessage {
+    pub(crate) patient_id: AccountId,
+    pub(crate) doctor_id: AccountId,
+    pub(crate) timestamp: u64,
+    pub(crate) message: String,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyOrder {
+    pub(crate) medicine_id: u32,
+    pub(crate) price: u128,
+    pub(crate) payment_amount: u64,
+    pub(crate) quantity: u128,
+    pub(crate) patient_id: u64,
+    pub(crate) date: u64,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyNotification {
+    pub(crate) account_id: AccountId,
+    pub(crate) message: String,
+    pub(crate) timestamp: u64,
+}
+
+// Contract state of the first deployment. `patients` and `prescriptions` were
+// both created with the `b"p"` prefix, so their elements share storage slots.
+#[near(serializers = [borsh])]
+pub(crate) struct OldContract {
+    pub(crate) owner: AccountId,
+    pub(crate) users: IterableSet<AccountId>,
+    pub(crate) patients: Vector<LegacyPatient>,
+    pub(crate) no_of_patients: u32,
+    pub(crate) doctors: Vector<LegacyDoctor>,
+    pub(crate) no_of_doctors: u32,
+    pub(crate) drugs: Vector<LegacyMedicine>,
+    pub(crate) no_of_drugs: u32,
+    pub(crate) prescriptions: Vector<LegacyPrescription>,
+    pub(crate) no_of_prescriptions: u32,
+    pub(crate) appointments: Vector<LegacyAppointment>,
+    pub(crate) no_of_appointments: u32,
+    pub(crate) notifications: Vector<LegacyNotification>,
+    pub(crate) no_of_notifications: u32,
+    pub(crate) orders: Vector<LegacyOrder>,
+    pub(crate) messages: Vector<LegacyMessage>,
+    pub(crate) appointment_fee: u128,
+    pub(crate) registration_fee: u128,
+}
+
+pub(crate) const LEGACY_SHARED_PREFIX: &[u8] = b"p";
+
+impl From<LegacyPatient> for Patient {
+    fn from(patient: LegacyPatient) -> Self {
+        Self {
+            id: patient.id,
+            title: patient.title,
+            first_name: patient.first_name,
+            last_name: patient.last_name,
+            gender: patient.gender,
+            condition: patient.condition,
+            phone: patient.phone,
+            email: patient.email,
+            dob: patient.dob,
+            city: patient.city,
+            address: patient.address,
+            doctor: patient.doctor,
+            profile_pic: patient.profile_pic,
+            account_id: patient.account_id,
+            message: patient.message,
+            bought_medicine: patient.bought_medicine.into_iter().map(|id| id as u32).collect(),
+            registration_fee_paid: 0,
+        }
+    }
+}
+
+impl From<LegacyDoctor> for Doctor {
+    fn from(doctor: LegacyDoctor) -> Self {
+        Self {
+            id: doctor.id,
+            title: doctor.title,
+            first_name: doctor.first_name,
+            last_name: doctor.last_name,
+            gender: doctor.gender,
+            designation: doctor.designation,
+            last_work: doctor.last_work,
+            email: doctor.email,
+            college_name: doctor.college_name,
+            college_id: doctor.college_id,
+            joining_year: doctor.joining_year,
+            end_year: doctor.end_year,
+            specialization: doctor.specialization,
+            registration_id: doctor.registration_id,
+            college_address: doctor.college_address,
+            account_id: doctor.account_id,
+            profile_pic: doctor.profile_pic,
+            bio: doctor.bio,
+            appointment_counts: doctor.appointment_counts,
+            successful_treaments: doctor.successful_treaments,
+            is_approved: doctor.is_approved,
+            registration_fee_paid: 0,
+            rating_sum: 0,
+            rating_count: 0,
+            earnings: 0,
+            total_earned: 0,
+            total_withdrawn: 0,
+        }
+    }
+}
+
+impl From<LegacyMedicine> for Medicine {
+    fn from(medicine: LegacyMedicine) -> Self {
+        Self {
+            id: medicine.id,
+            doctor_id: medicine.doctor_id,
+            name: medicine.name,
+            brand: medicine.brand,
+            manufacturer: medicine.manufacturer,
+            manufacturing_date: medicine.manufacturing_date,
+            expiry_date: medicine.expiry_date,
+            company_email: medicine.company_email,
+            // Never validated by the first deployment
+            discount: medicine.discount.min(100),
+            manufacturer_address: medicine.manufacturer_address,
+            price: medicine.price,
+            quantity: medicine.quantity,
+            current_location: medicine.current_location,
+            phone_no: medicine.phone_no,
+            image: medicine.image,
+            description: medicine.description,
+            availability: medicine.availability,
+            requires_prescription: false,
+            controlled_schedule: None,
+            manufacturer_account: None,
+            recall_id: None,
+        }
+    }
+}
+
+impl From<LegacyPrescription> for Prescription {
+    fn from(prescription: LegacyPrescription) -> Self {
+        Self {
+            id: prescription.id,
+            medicine_id: prescription.medicine_id,
+            patient_id: UNATTRIBUTED_PATIENT,
+            doctor_id: prescription.doctor_id,
+            date: prescription.date,
+            // Legacy prescriptions named only the medicine; they cannot be
+            // filled until a doctor writes a new one.
+            dosage: String::new(),
+            frequency: String::new(),
+            duration_days: 0,
+            quantity: 0,
+            refills: 0,
+            expires_at: prescription.date,
+            fills_used: 0,
+            instructions: String::new(),
+            appointment_id: None,
+        }
+    }
+}
+
+impl From<LegacyAppointment> for Appointment {
+    fn from(appointment: LegacyAppointment) -> Self {
+        let status = match appointment.status {
+            LegacyAppointmentStatus::Pending => AppointmentStatus::Requested,
+            LegacyAppointmentStatus::Completed => AppointmentStatus::Completed,
+        };
+        Self {
+            id: appointment.id,
+            patient_id: UNATTRIBUTED_PATIENT,
+            doctor_id: appointment.doctor_id,
+            slot: legacy_slot(&appointment.appointment_date, &appointment.from, &appointment.to),
+            condition: appointment.condition,
+            status,
+            message: appointment.message,
+            // Legacy appointments carry no transition times; record the
+            // migration itself as the step that set their status.
+            history: vec![StatusChange {
+                status,
+                actor: env::current_account_id(),
+                timestamp: env::block_timestamp(),
+            }],
+            rescheduled_from: None,
+            rescheduled_to: None,
+            fee_paid: 0,
+            fee_refunded: 0,
+            escrow: 0,
+        }
+    }
+}
+
+// Legacy appointments were booked from the frontend form as "YYYY-MM-DD"
+// and "HH:MM" strings. Anything unreadable falls back to the epoch/midnight.
+fn legacy_slot(date: &str, from: &str, to: &str) -> Slot {
+    let numbers = |text: &str, separator: char| -> Option<Vec<i64>> {
+        text.trim().split(separator).map(|part| part.parse().ok()).collect()
+    };
+    let date = parse_date(date).unwrap_or(0);
+    let minute = |time: &str| match numbers(time, ':').as_deref() {
+        Some(&[hour, minute]) if (0..24).contains(&hour) && (0..60).contains(&minute) => (hour * 60 + minute) as u32,
+        _ => 0,
+    };
+    let start_minute = minute(from);
+    Slot {
+        date,
+        start_minute,
+        end_minute: minute(to).max(start_minute),
+    }
+}
+
+impl From<LegacyMessage> for Message {
+    fn from(message: LegacyMessage) -> Self {
+        Self {
+            patient_id: message.patient_id,
+            doctor_id: message.doctor_id,
+            timestamp: message.timestamp,
+            message: message.message,
+        }
+    }
+}
+
+impl From<LegacyOrder> for Order {
+    fn from(order: LegacyOrder) -> Self {
+        Self {
+            id: 0,
+            medicine_id: order.medicine_id,
+            patient_id: UNATTRIBUTED_PATIENT,
+            price: order.price,
+            discount: 0,
+            quantity: order.quantity,
+            payment_amount: order.payment_amount as u128,
+            status: OrderStatus::Paid,
+            date: order.date,
+            prescription_id: None,
+            pharmacy_id: None,
+            batches: vec![],
+            serials: vec![],
+        }
+    }
+}
+
+impl From<LegacyNotification> for Notification {
+    fn from(notification: LegacyNotification) -> Self {
+        Self {
+            account_id: notification.account_id,
+            message: notification.message,
+            timestamp: notification.timestamp,
+        }
+    }
+}
+
+// A record recovered from the slots shared by the legacy patient and
+// prescription vectors. Whichever vector pushed last owns the slot.
+enum SharedSlot {
+    Patient(Box<LegacyPatient>),
+    Prescription(LegacyPrescription),
+}
+
+fn read_shared_slot(index: u32) -> Option<SharedSlot> {
+    let mut key = LEGACY_SHARED_PREFIX.to_vec();
+    key.extend_from_slice(&index.to_le_bytes());
+    let raw = env::storage_read(&key)?;
+    env::storage_remove(&key);
+
+    if let Ok(patient) = LegacyPatient::try_from_slice(&raw) {
+        Some(SharedSlot::Patient(Box::new(patient)))
+    } else if let Ok(prescription) = LegacyPrescription::try_from_slice(&raw) {
+        Some(SharedSlot::Prescription(prescription))
+    } else {
+        None
+    }
+}
+
+// Legacy `update_patient_medical` pushed a copy of the original record with one
+// extra history entry instead of updating it, so every copy shares the original
+// history as a prefix. Fold the entries each copy added onto the merged record.
+fn merge_patient_copy(merged: &mut LegacyPatient, copy: LegacyPatient) {
+    let shared = merged
+        .medical_history
+        .iter()
+        .zip(copy.medical_history.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    merged
+        .medical_history
+        .extend(copy.medical_history.into_iter().skip(shared));
+    for medicine_id in copy.bought_medicine {
+        if !merged.bought_medicine.contains(&medicine_id) {
+            merged.bought_medicine.push(medicine_id);
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    // Upgrades the first testnet deployment to the keyed storage layout.
+    // Patient and prescription records that survived the shared `b"p"` prefix
+    // are separated, duplicated copies are collapsed and every collection is
+    // rewritten under its `StorageKey` prefix.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let mut old: OldContract = env::state_read().expect("No legacy state to migrate");
+
+        let mut contract = Self {
+            owner: old.owner.clone(),
+            appointment_fee: old.appointment_fee,
+            registration_fee: old.registration_fee,
+            ..Default::default()
+        };
+        // Legacy `users` were the admin set
+        for account_id in old.users.iter() {
+            contract.internal_grant_role(account_id, Role::Admin);
+        }
+        old.users.clear();
+
+        // Patients were all stored with id 0, so identify them by account and
+        // hand out fresh ids in registration order.
+        let shared_len = old.patients.len().max(old.prescriptions.len());
+        let mut patients: Vec<LegacyPatient> = Vec::new();
+        let mut patient_index: HashMap<AccountId, usize> = HashMap::new();
+        let mut prescriptions: Vec<LegacyPrescription> = Vec::new();
+        let mut lost_slots = 0u32;
+        for index in 0..shared_len {
+            match read_shared_slot(index) {
+                Some(SharedSlot::Patient(patient)) => match patient_index.get(&patient.account_id) {
+                    Some(&position) => merge_patient_copy(&mut patients[position], *patient),
+                    None => {
+                        patient_index.insert(patient.account_id.clone(), patients.len());
+                        patients.push(*patient);
+                    }
+                },
+                Some(SharedSlot::Prescription(prescription)) => prescriptions.push(prescription),
+                None => lost_slots += 1,
+            }
+        }
+        for mut patient in patients {
+            patient.id = contract.no_of_patients;
+            contract.internal_grant_role(&patient.account_id, Role::Patient);
+            contract.patient_by_account.insert(patient.account_id.clone(), patient.id);
+            // Free-text history entries become Note records
+            for text in std::mem::take(&mut patient.medical_history) {
+                contract.insert_medical_record(MedicalRecordEntry::note(patient.id, text));
+            }
+            contract.patients.insert(patient.id, patient.into());
+            contract.no_of_patients += 1;
+        }
+
+        // Prescription ids were supplied by callers; keep them unless taken.
+        let mut next_prescription_id = prescriptions.iter().map(|p| p.id + 1).max().unwrap_or(0);
+        for mut prescription in prescriptions {
+            if contract.prescriptions.contains_key(&prescription.id) {
+                prescription.id = next_prescription_id;
+                next_prescription_id += 1;
+            }
+            contract.prescriptions.insert(prescription.id, prescription.into());
+        }
+        contract.no_of_prescriptions = next_prescription_id;
+
+        // Approvals pushed updated copies, so the last copy of a doctor wins.
+        for doctor in old.doctors.drain(..) {
+            if doctor.is_approved {
+                contract.internal_grant_role(&doctor.account_id, Role::Doctor);
+            }
+            contract.doctors.insert(doctor.id, doctor.into());
+        }
+        // Accounts that registered more than once resolve to their first profile
+        let mut doctor_ids: Vec<u32> = contract.doctors.keys().copied().collect();
+        doctor_ids.sort();
+        for id in doctor_ids {
+            let account_id = contract.doctors[&id].account_id.clone();
+            if !contract.doctor_by_account.contains_key(&account_id) {
+                contract.doctor_by_account.insert(account_id, id);
+            }
+        }
+        contract.no_of_doctors = old.no_of_doctors;
+
+        // Legacy stock becomes an opening batch. Unreadable dates fall back to
+        // the epoch, so such stock counts as expired until it is checked.
+        for medicine in old.drugs.drain(..) {
+            if medicine.quantity > 0 {
+                contract.insert_batch(
+                    Batch {
+                        id: 0,
+                        medicine_id: medicine.id,
+                        holder: StockHolder::Catalog,
+                        lot_number: batches::OPENING_LOT_NUMBER.to_string(),
+                        manufactured_at: date_timestamp(&medicine.manufacturing_date).unwrap_or(0),
+                        expires_at: date_timestamp(&medicine.expiry_date).unwrap_or(0),
+                        quantity: medicine.quantity,
+                        remaining: medicine.quantity,
+                        recall_id: None,
+                        custodian: old.owner.clone(),
+                        pending_transfer: None,
+                        serials_registered: 0,
+                    },
+                    medicine.current_location.clone(),
+                );
+            }
+            contract.drugs.insert(medicine.id, medicine.into());
+        }
+        contract.no_of_drugs = old.no_of_drugs;
+
+        // Appointment ids were taken from the notification counter and are not
+        // unique, so renumber them by booking order.
+        for mut appointment in old.appointments.drain(..) {
+            appointment.id = contract.no_of_appointments;
+            contract.insert_appointment(appointment.into());
+        }
+
+        for notification in old.notifications.drain(..) {
+            contract.notifications.push(notification.into());
+        }
+        contract.no_of_notifications = contract.notifications.len();
+
+        for order in old.orders.drain(..) {
+            let mut order: Order = order.into();
+            order.id = contract.no_of_orders;
+            contract.orders_by_medicine.entry(order.medicine_id).or_default().push(order.id);
+            contract.orders.insert(order.id, order);
+            contract.no_of_orders += 1;
+        }
+
+        for message in old.messages.drain(..) {
+            let message: Message = message.into();
+            contract
+                .conversations
+                .entry((message.patient_id.clone(), message.doctor_id.clone()))
+                .or_default()
+                .push(contract.messages.len());
+            contract.messages.push(message);
+        }
+
+        log!(
+            "Migrated {} patients, {} prescriptions, {} doctors, {} medicines and {} appointments; {} shared slots were unreadable",
+            contract.patients.len(),
+            contract.prescriptions.len(),
+            contract.doctors.len(),
+            contract.drugs.len(),
+            contract.appointments.len(),
+            lost_slots
+        );
+
+        contract
+    }
+}
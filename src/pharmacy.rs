@@ -0,0 +1,231 @@
+use crate::*;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct PharmacyInput {
+    pub(crate) name: String,
+    pub(crate) license_number: String,
+    pub(crate) email: String,
+    pub(crate) phone: String,
+    pub(crate) city: String,
+    pub(crate) address: String,
+    pub(crate) account_id: AccountId,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Pharmacy {
+    pub(crate) id: u32,
+    pub(crate) name: String,
+    pub(crate) license_number: String,
+    pub(crate) email: String,
+    pub(crate) phone: String,
+    pub(crate) city: String,
+    pub(crate) address: String,
+    // Operator account; receives the payment for orders routed here
+    pub(crate) account_id: AccountId,
+    pub(crate) is_approved: bool,
+}
+
+// What one pharmacy holds of one catalog medicine, at its own price
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct PharmacyStock {
+    pub(crate) pharmacy_id: u32,
+    pub(crate) medicine_id: u32,
+    pub(crate) quantity: u128,
+    pub(crate) price: u128,
+    // Percentage off `price`
+    pub(crate) discount: u128,
+}
+
+#[near(serializers = [json])]
+#[derive(Clone)]
+pub struct PharmacyOffer {
+    pub(crate) pharmacy: Pharmacy,
+    pub(crate) stock: PharmacyStock,
+}
+
+impl Contract {
+    pub(crate) fn caller_pharmacy_id(&self) -> u32 {
+        let id = *self
+            .pharmacy_by_account
+            .get(&env::predecessor_account_id())
+            .expect("Caller has no pharmacy profile");
+        require!(self.pharmacies[&id].is_approved, "Pharmacy is not approved");
+        id
+    }
+
+    // Takes `quantity` off the pharmacy's stock total and returns its unit
+    // price and discount
+    pub(crate) fn take_pharmacy_stock(&mut self, pharmacy_id: u32, medicine_id: u32, quantity: u128) -> (u128, u128) {
+        let pharmacy = self.pharmacies.get(&pharmacy_id).expect("Pharmacy not found");
+        require!(pharmacy.is_approved, "Pharmacy is not approved");
+        let stock = self
+            .pharmacy_stock
+            .get_mut(&(pharmacy_id, medicine_id))
+            .expect("Pharmacy does not stock this medicine");
+        require!(stock.quantity >= quantity, "Not enough stock");
+
+        stock.quantity -= quantity;
+        stock.emit_changed();
+        (stock.price, stock.discount)
+    }
+
+    pub(crate) fn add_pharmacy_stock(&mut self, pharmacy_id: u32, medicine_id: u32, quantity: u128) {
+        let stock = self.pharmacy_stock.get_mut(&(pharmacy_id, medicine_id)).expect("Pharmacy stock not found");
+        stock.quantity += quantity;
+        stock.emit_changed();
+    }
+}
+
+impl PharmacyStock {
+    fn emit_changed(&self) {
+        MediEvent::PharmacyStockChanged {
+            pharmacy_id: self.pharmacy_id,
+            medicine_id: self.medicine_id,
+            quantity: U128(self.quantity),
+            price: U128(self.price),
+        }
+        .emit();
+    }
+}
+
+#[near]
+impl Contract {
+    #[payable]
+    pub fn register_pharmacy(&mut self, pharmacy: PharmacyInput) -> u32 {
+        self.assert_self_or_role(&pharmacy.account_id, &[Role::Admin, Role::Staff]);
+        require!(
+            !self.pharmacy_by_account.contains_key(&pharmacy.account_id),
+            "Account already has a pharmacy profile"
+        );
+        let initial_storage = env::storage_usage();
+        let fee_paid = self.collect_fee(self.registration_fee);
+        self.registration_fees_collected += fee_paid;
+
+        let pharmacy = Pharmacy {
+            id: self.no_of_pharmacies,
+            name: pharmacy.name,
+            license_number: pharmacy.license_number,
+            email: pharmacy.email,
+            phone: pharmacy.phone,
+            city: pharmacy.city,
+            address: pharmacy.address,
+            account_id: pharmacy.account_id,
+            is_approved: false,
+        };
+        let id = pharmacy.id;
+        MediEvent::PharmacyRegistered {
+            pharmacy_id: id,
+            account_id: pharmacy.account_id.clone(),
+        }
+        .emit();
+        self.pharmacy_by_account.insert(pharmacy.account_id.clone(), id);
+        self.pharmacies.insert(id, pharmacy);
+        self.no_of_pharmacies += 1;
+
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+        id
+    }
+
+    pub fn approve_pharmacy(&mut self, id: u32) {
+        self.assert_role(&[Role::Admin]);
+        let pharmacy = self.pharmacies.get_mut(&id).expect("Pharmacy not found");
+        pharmacy.is_approved = true;
+        let account_id = pharmacy.account_id.clone();
+
+        MediEvent::PharmacyApproved {
+            pharmacy_id: id,
+            account_id: account_id.clone(),
+        }
+        .emit();
+        self.record_audit(None, AuditAction::PharmacyApproved);
+        self.notify(account_id, "Your pharmacy has been approved.".to_string());
+    }
+
+    // Sets the caller's pricing for a catalog medicine. Stock arrives in
+    // batches, see `add_batch`.
+    pub fn set_pharmacy_price(&mut self, medicine_id: u32, price: u128, discount: u128) {
+        let pharmacy_id = self.caller_pharmacy_id();
+        require!(self.drugs.contains_key(&medicine_id), "Medicine not found");
+        require!(discount <= 100, "Discount is a percentage between 0 and 100");
+        let initial_storage = env::storage_usage();
+
+        if !self.pharmacy_stock.contains_key(&(pharmacy_id, medicine_id)) {
+            self.pharmacy_medicines.entry(pharmacy_id).or_default().push(medicine_id);
+            self.stockists.entry(medicine_id).or_default().push(pharmacy_id);
+        }
+        let stock = self.pharmacy_stock.entry((pharmacy_id, medicine_id)).or_insert(PharmacyStock {
+            pharmacy_id,
+            medicine_id,
+            quantity: 0,
+            price,
+            discount,
+        });
+        stock.price = price;
+        stock.discount = discount;
+        stock.emit_changed();
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
+
+    pub fn get_pharmacy(&self, pharmacy_id: u32) -> Option<Pharmacy> {
+        self.pharmacies.get(&pharmacy_id).cloned()
+    }
+
+    pub fn get_pharmacy_by_account(&self, account_id: AccountId) -> Option<Pharmacy> {
+        self.pharmacy_by_account
+            .get(&account_id)
+            .and_then(|id| self.pharmacies.get(id))
+            .cloned()
+    }
+
+    pub fn get_all_pharmacies(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Pharmacy> {
+        paginate(self.pharmacies.values(), from_index, limit)
+    }
+
+    pub fn get_pharmacies_count(&self) -> u32 {
+        self.pharmacies.len()
+    }
+
+    pub fn get_pharmacy_inventory(&self, pharmacy_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<PharmacyStock> {
+        paginate(
+            self.pharmacy_medicines
+                .get(&pharmacy_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|medicine_id| self.pharmacy_stock.get(&(pharmacy_id, *medicine_id))),
+            from_index,
+            limit,
+        )
+    }
+
+    // Approved pharmacies in `city` that have the medicine in stock; none
+    // while the medicine is recalled
+    pub fn get_pharmacies_with_stock(
+        &self,
+        medicine_id: u32,
+        city: String,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<PharmacyOffer> {
+        let offers: Vec<PharmacyOffer> = self
+            .stockists
+            .get(&medicine_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|pharmacy_id| {
+                let pharmacy = self.pharmacies.get(pharmacy_id)?;
+                let stock = self.pharmacy_stock.get(&(*pharmacy_id, medicine_id))?;
+                (pharmacy.is_approved
+                    && self.sellable_quantity(StockHolder::Pharmacy(*pharmacy_id), medicine_id) > 0
+                    && pharmacy.city.trim().eq_ignore_ascii_case(city.trim()))
+                    .then(|| PharmacyOffer {
+                        pharmacy: pharmacy.clone(),
+                        stock: stock.clone(),
+                    })
+            })
+            .collect();
+        paginate(offers.iter(), from_index, limit)
+    }
+}
@@ -0,0 +1,135 @@
+use crate::*;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Role {
+    // Held implicitly by `Contract::owner`, never stored in `roles`
+    Owner,
+    Admin,
+    Doctor,
+    Patient,
+    Pharmacist,
+    LabPartner,
+    Staff,
+    Manufacturer,
+}
+
+impl Role {
+    // Roles an admin may hand out; only the owner can grant or revoke Admin
+    fn grantable_by_admin(&self) -> bool {
+        !matches!(self, Role::Owner | Role::Admin)
+    }
+}
+
+impl Contract {
+    pub(crate) fn has_role_internal(&self, account_id: &AccountId, role: Role) -> bool {
+        match role {
+            Role::Owner => account_id == &self.owner,
+            _ => self.roles.get(account_id).is_some_and(|roles| roles.contains(&role)),
+        }
+    }
+
+    // The owner passes every guard; everybody else needs one of `roles`
+    pub(crate) fn caller_has_any_role(&self, roles: &[Role]) -> bool {
+        let caller_id = env::predecessor_account_id();
+        caller_id == self.owner || roles.iter().any(|role| self.has_role_internal(&caller_id, *role))
+    }
+
+    pub(crate) fn assert_role(&self, roles: &[Role]) {
+        require!(
+            self.caller_has_any_role(roles),
+            format!("Only accounts with one of the roles {:?} can call this method", roles)
+        );
+    }
+
+    pub(crate) fn assert_owner(&self) {
+        require!(env::predecessor_account_id() == self.owner, "Only the owner can call this method");
+    }
+
+    pub(crate) fn internal_grant_role(&mut self, account_id: &AccountId, role: Role) -> bool {
+        require!(role != Role::Owner, "The Owner role is transferred with update_admin_address");
+        let roles = self.roles.entry(account_id.clone()).or_default();
+        if roles.contains(&role) {
+            return false;
+        }
+        roles.push(role);
+        roles.sort();
+        MediEvent::RoleGranted {
+            account_id: account_id.clone(),
+            role,
+        }
+        .emit();
+        true
+    }
+
+    pub(crate) fn internal_revoke_role(&mut self, account_id: &AccountId, role: Role) -> bool {
+        let Some(roles) = self.roles.get_mut(account_id) else {
+            return false;
+        };
+        let before = roles.len();
+        roles.retain(|held| *held != role);
+        let removed = roles.len() != before;
+        if roles.is_empty() {
+            self.roles.remove(account_id);
+        }
+        if removed {
+            MediEvent::RoleRevoked {
+                account_id: account_id.clone(),
+                role,
+            }
+            .emit();
+        }
+        removed
+    }
+
+    fn assert_can_manage_role(&self, role: Role) {
+        if role.grantable_by_admin() {
+            self.assert_role(&[Role::Admin]);
+        } else {
+            self.assert_owner();
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_can_manage_role(role);
+        if self.internal_grant_role(&account_id, role) {
+            self.record_audit(None, AuditAction::RoleGranted);
+        }
+    }
+
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_can_manage_role(role);
+        if self.internal_revoke_role(&account_id, role) {
+            self.record_audit(None, AuditAction::RoleRevoked);
+        }
+    }
+
+    pub fn has_role(&self, account_id: AccountId, role: Role) -> bool {
+        self.has_role_internal(&account_id, role)
+    }
+
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        let mut roles = self.roles.get(&account_id).cloned().unwrap_or_default();
+        if account_id == self.owner {
+            roles.insert(0, Role::Owner);
+        }
+        roles
+    }
+
+    pub fn get_role_members(&self, role: Role, from_index: Option<u32>, limit: Option<u32>) -> Vec<AccountId> {
+        if role == Role::Owner {
+            return vec![self.owner.clone()];
+        }
+        paginate(
+            self.roles
+                .iter()
+                .filter(|(_, roles)| roles.contains(&role))
+                .map(|(account_id, _)| account_id),
+            from_index,
+            limit,
+        )
+    }
+}
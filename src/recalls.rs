@@ -0,0 +1,144 @@
+use crate::*;
+
+// A medicine, or one lot of it, pulled off the market
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Recall {
+    pub(crate) id: u32,
+    pub(crate) medicine_id: u32,
+    // `None` recalls every lot of the medicine
+    pub(crate) lot_number: Option<String>,
+    pub(crate) reason: String,
+    pub(crate) recalled_by: AccountId,
+    pub(crate) timestamp: u64,
+}
+
+impl Contract {
+    fn assert_can_recall(&self, medicine_id: u32) {
+        require!(
+            self.caller_is_manufacturer(medicine_id) || self.caller_has_any_role(&[Role::Admin]),
+            "Only an admin or the medicine's manufacturer can recall it"
+        );
+    }
+
+    // Patients whose orders took stock the recall covers, and the doctors who
+    // prescribed it to them
+    fn recall_recipients(&self, medicine_id: u32, batch_ids: Option<&[u32]>) -> Vec<AccountId> {
+        let mut recipients: Vec<AccountId> = vec![];
+        for order_id in self.orders_by_medicine.get(&medicine_id).into_iter().flatten() {
+            let order = &self.orders[order_id];
+            let affected = order.status != OrderStatus::Failed
+                && batch_ids.map_or(true, |ids| order.batches.iter().any(|(batch_id, _)| ids.contains(batch_id)));
+            if !affected {
+                continue;
+            }
+            if let Some(patient) = self.patients.get(&order.patient_id) {
+                recipients.push(patient.account_id.clone());
+            }
+            let doctor = order
+                .prescription_id
+                .and_then(|id| self.prescriptions.get(&id))
+                .and_then(|prescription| self.doctors.get(&prescription.doctor_id));
+            if let Some(doctor) = doctor {
+                recipients.push(doctor.account_id.clone());
+            }
+        }
+        recipients.sort();
+        recipients.dedup();
+        recipients
+    }
+
+    fn internal_recall(&mut self, medicine_id: u32, lot_number: Option<String>, reason: String, batch_ids: Option<Vec<u32>>) -> u32 {
+        require!(!reason.trim().is_empty(), "Recall reason must not be empty");
+        let recall = Recall {
+            id: self.recalls.len(),
+            medicine_id,
+            lot_number,
+            reason,
+            recalled_by: env::predecessor_account_id(),
+            timestamp: env::block_timestamp(),
+        };
+        let id = recall.id;
+        match &batch_ids {
+            Some(batch_ids) => {
+                for batch_id in batch_ids {
+                    self.batches.get_mut(batch_id).unwrap().recall_id.get_or_insert(id);
+                }
+            }
+            None => self.drugs.get_mut(&medicine_id).unwrap().recall_id = Some(id),
+        }
+
+        let medicine_name = self.drugs[&medicine_id].name.clone();
+        let message = match &recall.lot_number {
+            Some(lot_number) => format!("{} lot {} has been recalled: {}", medicine_name, lot_number, recall.reason),
+            None => format!("{} has been recalled: {}", medicine_name, recall.reason),
+        };
+        for account_id in self.recall_recipients(medicine_id, batch_ids.as_deref()) {
+            self.notify(account_id, message.clone());
+        }
+
+        MediEvent::MedicineRecalled {
+            recall_id: id,
+            medicine_id,
+            lot_number: recall.lot_number.clone(),
+            reason: recall.reason.clone(),
+        }
+        .emit();
+        self.recalls.push(recall);
+        self.record_audit(None, AuditAction::MedicineRecalled);
+        id
+    }
+}
+
+#[near]
+impl Contract {
+    // Links a catalog medicine to the account of its manufacturer, who may
+    // then register batches of it and recall it
+    pub fn set_medicine_manufacturer(&mut self, medicine_id: u32, account_id: AccountId) {
+        self.assert_role(&[Role::Admin]);
+        let medicine = self.drugs.get_mut(&medicine_id).expect("Medicine not found");
+        medicine.manufacturer_account = Some(account_id.clone());
+        if self.internal_grant_role(&account_id, Role::Manufacturer) {
+            self.record_audit(None, AuditAction::RoleGranted);
+        }
+    }
+
+    // Stops every sale of the medicine and warns the patients who bought it
+    pub fn recall_medicine(&mut self, medicine_id: u32, reason: String) -> u32 {
+        self.assert_can_recall(medicine_id);
+        require!(self.drugs[&medicine_id].recall_id.is_none(), "Medicine has already been recalled");
+        self.internal_recall(medicine_id, None, reason, None)
+    }
+
+    // Stops sales of one lot, wherever it is stocked, and warns the patients
+    // who were dispensed from it
+    pub fn recall_lot(&mut self, medicine_id: u32, lot_number: String, reason: String) -> u32 {
+        self.assert_can_recall(medicine_id);
+        let batch_ids: Vec<u32> = self
+            .batch_by_lot
+            .get(&(medicine_id, lot_number.clone()))
+            .cloned()
+            .expect("Lot not found");
+        require!(
+            batch_ids.iter().any(|id| self.batches[id].recall_id.is_none()),
+            "Lot has already been recalled"
+        );
+        self.internal_recall(medicine_id, Some(lot_number), reason, Some(batch_ids))
+    }
+
+    pub fn get_recall(&self, recall_id: u32) -> Option<Recall> {
+        self.recalls.get(recall_id).cloned()
+    }
+
+    pub fn get_medicine_recalls(&self, medicine_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<Recall> {
+        paginate(
+            self.recalls.iter().filter(|recall| recall.medicine_id == medicine_id),
+            from_index,
+            limit,
+        )
+    }
+
+    pub fn get_recalls(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<Recall> {
+        paginate(self.recalls.iter(), from_index, limit)
+    }
+}
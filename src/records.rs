@@ -0,0 +1,200 @@
+use crate::*;
+
+pub const MAX_RECORD_TEXT_LENGTH: usize = 10_000;
+pub const MAX_RECORD_ATTACHMENTS: usize = 10;
+// Attachments are content hashes, so this is generous
+pub const MAX_ATTACHMENT_LENGTH: usize = 128;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecordKind {
+    Diagnosis,
+    Procedure,
+    Note,
+    Allergy,
+    Lab,
+}
+
+// One entry of a patient's medical history. Entries are never edited or
+// deleted: an amendment is a new entry that supersedes the old one.
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct MedicalRecordEntry {
+    pub(crate) id: u32,
+    pub(crate) patient_id: u32,
+    pub(crate) kind: RecordKind,
+    // `None` for entries migrated from the free-text history
+    pub(crate) doctor_id: Option<u32>,
+    pub(crate) appointment_id: Option<u32>,
+    pub(crate) timestamp: u64,
+    pub(crate) text: String,
+    // Content hashes of files stored off-chain
+    pub(crate) attachments: Vec<String>,
+    pub(crate) supersedes: Option<u32>,
+    pub(crate) superseded_by: Option<u32>,
+    // Set instead of `text` for entries written encrypted
+    pub(crate) encrypted: Option<EncryptedPayload>,
+}
+
+impl MedicalRecordEntry {
+    // Entries from the legacy `Vec<String>` history. The id is assigned by
+    // `insert_medical_record`.
+    pub(crate) fn note(patient_id: u32, text: String) -> Self {
+        Self {
+            id: 0,
+            patient_id,
+            kind: RecordKind::Note,
+            doctor_id: None,
+            appointment_id: None,
+            timestamp: env::block_timestamp(),
+            text,
+            attachments: vec![],
+            supersedes: None,
+            superseded_by: None,
+            encrypted: None,
+        }
+    }
+}
+
+impl Contract {
+    // Stores `entry` under the next record id and lists it in the patient's history
+    pub(crate) fn insert_medical_record(&mut self, mut entry: MedicalRecordEntry) -> u32 {
+        let id = self.no_of_medical_records;
+        entry.id = id;
+        self.medical_histories.entry(entry.patient_id).or_default().push(id);
+        self.medical_records.insert(id, entry);
+        self.no_of_medical_records += 1;
+        id
+    }
+
+    // Adds an entry authored by the calling doctor after checking write consent
+    pub(crate) fn internal_add_medical_record(
+        &mut self,
+        patient_id: u32,
+        kind: RecordKind,
+        text: String,
+        appointment_id: Option<u32>,
+        attachments: Vec<String>,
+        supersedes: Option<u32>,
+        encrypted: Option<EncryptedPayload>,
+    ) -> u32 {
+        self.assert_medical_access(patient_id, Access::Write);
+        require!(
+            text.len() <= MAX_RECORD_TEXT_LENGTH,
+            format!("Record text is limited to {} bytes", MAX_RECORD_TEXT_LENGTH)
+        );
+        require!(
+            attachments.len() <= MAX_RECORD_ATTACHMENTS,
+            format!("A record can have at most {} attachments", MAX_RECORD_ATTACHMENTS)
+        );
+        require!(
+            attachments.iter().all(|attachment| attachment.len() <= MAX_ATTACHMENT_LENGTH),
+            format!("Attachments are limited to {} bytes", MAX_ATTACHMENT_LENGTH)
+        );
+        if let Some(payload) = &encrypted {
+            require!(text.is_empty(), "Encrypted records must not carry plaintext text");
+            self.assert_payload_readers(patient_id, payload);
+        }
+        let doctor_id = *self
+            .doctor_by_account
+            .get(&env::predecessor_account_id())
+            .expect("Caller has no doctor profile");
+
+        let id = self.insert_medical_record(MedicalRecordEntry {
+            id: 0,
+            patient_id,
+            kind,
+            doctor_id: Some(doctor_id),
+            appointment_id,
+            timestamp: env::block_timestamp(),
+            text,
+            attachments,
+            supersedes,
+            superseded_by: None,
+            encrypted,
+        });
+
+        MediEvent::MedicalHistoryUpdated {
+            patient_id,
+            entry_id: id,
+            actor: env::predecessor_account_id(),
+        }
+        .emit();
+        id
+    }
+}
+
+#[near]
+impl Contract {
+    // Returns the id of the new entry. Sensitive entries pass an empty `text`
+    // and the content in `encrypted`, readable by the patient at least.
+    pub fn add_medical_record(
+        &mut self,
+        patient_id: u32,
+        kind: RecordKind,
+        text: String,
+        appointment_id: Option<u32>,
+        attachments: Option<Vec<String>>,
+        encrypted: Option<EncryptedPayload>,
+    ) -> u32 {
+        if let Some(appointment_id) = appointment_id {
+            let appointment = self.appointments.get(&appointment_id).expect("Appointment not found");
+            require!(
+                appointment.patient_id == patient_id
+                    && self.doctors[&appointment.doctor_id].account_id == env::predecessor_account_id(),
+                "Appointment does not belong to this patient and doctor"
+            );
+        }
+        let initial_storage = env::storage_usage();
+
+        let id = self.internal_add_medical_record(
+            patient_id,
+            kind,
+            text,
+            appointment_id,
+            attachments.unwrap_or_default(),
+            None,
+            encrypted,
+        );
+        self.record_audit(Some(patient_id), AuditAction::MedicalHistoryUpdated);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+        id
+    }
+
+    // Supersedes `entry_id` with a corrected entry of the same kind. Returns
+    // the id of the new entry; the old one stays readable.
+    pub fn amend_medical_record(
+        &mut self,
+        entry_id: u32,
+        text: String,
+        attachments: Option<Vec<String>>,
+        encrypted: Option<EncryptedPayload>,
+    ) -> u32 {
+        let previous = self.medical_records.get(&entry_id).expect("Medical record not found").clone();
+        require!(previous.superseded_by.is_none(), "Medical record has already been amended");
+        let initial_storage = env::storage_usage();
+
+        let id = self.internal_add_medical_record(
+            previous.patient_id,
+            previous.kind,
+            text,
+            previous.appointment_id,
+            attachments.unwrap_or_default(),
+            Some(entry_id),
+            encrypted,
+        );
+        self.medical_records.get_mut(&entry_id).unwrap().superseded_by = Some(id);
+        self.record_audit(Some(previous.patient_id), AuditAction::MedicalRecordAmended);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+        id
+    }
+
+    // Reads one entry, superseded or not. A change method for the same
+    // reason as `get_patient_medical_history`.
+    pub fn get_medical_record(&mut self, entry_id: u32) -> MedicalRecordEntry {
+        let entry = self.medical_records.get(&entry_id).expect("Medical record not found").clone();
+        self.assert_medical_access(entry.patient_id, Access::Read);
+        self.record_read_audit(entry.patient_id);
+        entry
+    }
+}
@@ -0,0 +1,100 @@
+use crate::*;
+use std::cmp::Ordering;
+
+pub const MAX_REVIEW_LENGTH: usize = 2_000;
+// Ratings at or above this count as a successful treatment
+pub const SUCCESSFUL_TREATMENT_RATING: u8 = 4;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Review {
+    pub(crate) appointment_id: u32,
+    pub(crate) patient_id: u32,
+    pub(crate) doctor_id: u32,
+    pub(crate) rating: u8,
+    pub(crate) review: Option<String>,
+    pub(crate) timestamp: u64,
+}
+
+// Orders doctors by average rating, then by number of ratings
+fn compare_ratings(a: &Doctor, b: &Doctor) -> Ordering {
+    let average_a = a.rating_sum as u64 * b.rating_count as u64;
+    let average_b = b.rating_sum as u64 * a.rating_count as u64;
+    average_a.cmp(&average_b).then(a.rating_count.cmp(&b.rating_count))
+}
+
+#[near]
+impl Contract {
+    // One rating per completed appointment, left by its patient
+    pub fn rate_appointment(&mut self, appointment_id: u32, rating: u8, review: Option<String>) {
+        let appointment = self.appointments.get(&appointment_id).expect("Appointment not found");
+        let patient = self.patients.get(&appointment.patient_id).expect("Patient not found");
+        require!(patient.account_id == env::predecessor_account_id(), "Only the patient can rate their appointment");
+        require!(appointment.status == AppointmentStatus::Completed, "Only completed appointments can be rated");
+        require!(!self.reviews.contains_key(&appointment_id), "Appointment has already been rated");
+        require!((1..=5).contains(&rating), "Rating must be between 1 and 5");
+        require!(
+            review.as_ref().map_or(true, |review| review.len() <= MAX_REVIEW_LENGTH),
+            format!("Review is limited to {} bytes", MAX_REVIEW_LENGTH)
+        );
+        let (patient_id, doctor_id) = (appointment.patient_id, appointment.doctor_id);
+        let initial_storage = env::storage_usage();
+
+        let doctor = self.doctors.get_mut(&doctor_id).expect("Doctor not found");
+        doctor.rating_sum += rating as u32;
+        doctor.rating_count += 1;
+        if rating >= SUCCESSFUL_TREATMENT_RATING {
+            doctor.successful_treaments += 1;
+        }
+
+        self.reviews.insert(
+            appointment_id,
+            Review {
+                appointment_id,
+                patient_id,
+                doctor_id,
+                rating,
+                review,
+                timestamp: env::block_timestamp(),
+            },
+        );
+        self.reviews_by_doctor.entry(doctor_id).or_default().push(appointment_id);
+        MediEvent::AppointmentRated {
+            appointment_id,
+            doctor_id,
+            rating,
+        }
+        .emit();
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
+
+    pub fn get_review(&self, appointment_id: u32) -> Option<Review> {
+        self.reviews.get(&appointment_id).cloned()
+    }
+
+    // Newest first
+    pub fn get_doctor_reviews(&self, doctor_id: u32, from_index: Option<u32>, limit: Option<u32>) -> Vec<Review> {
+        paginate(
+            self.reviews_by_doctor
+                .get(&doctor_id)
+                .into_iter()
+                .flatten()
+                .rev()
+                .filter_map(|appointment_id| self.reviews.get(appointment_id)),
+            from_index,
+            limit,
+        )
+    }
+
+    // Approved doctors of a specialization, best rated first. Doctors without
+    // ratings come last.
+    pub fn get_top_doctors(&self, specialization: String, from_index: Option<u32>, limit: Option<u32>) -> Vec<Doctor> {
+        let mut doctors: Vec<&Doctor> = self
+            .doctors
+            .values()
+            .filter(|doctor| doctor.is_approved && doctor.specialization.eq_ignore_ascii_case(&specialization))
+            .collect();
+        doctors.sort_by(|a, b| compare_ratings(b, a));
+        paginate(doctors.into_iter(), from_index, limit)
+    }
+}
@@ -0,0 +1,137 @@
+use crate::*;
+
+// Most serial hashes `register_serials` accepts in one call
+pub const MAX_SERIALS_PER_CALL: usize = 200;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct SerialRecord {
+    pub(crate) batch_id: u32,
+    // Order the pack was sold in, once dispensed
+    pub(crate) order_id: Option<u32>,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SerialStatus {
+    Genuine,
+    // Never registered by a manufacturer; possibly counterfeit
+    Unknown,
+    AlreadyDispensed,
+    Recalled,
+}
+
+impl Contract {
+    // Puts the packs of a failed order back on the shelf
+    pub(crate) fn restock_serials(&mut self, serials: &[String]) {
+        for serial in serials {
+            self.serials.get_mut(serial).expect("Serial not found").order_id = None;
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    // Called by the medicine's manufacturer with the hashes of the serial
+    // numbers printed on the batch's packs
+    pub fn register_serials(&mut self, batch_id: u32, serial_hashes: Vec<String>) {
+        let batch = self.batches.get(&batch_id).expect("Batch not found");
+        require!(
+            self.caller_is_manufacturer(batch.medicine_id),
+            "Only the medicine's manufacturer can register serials"
+        );
+        require!(
+            serial_hashes.len() <= MAX_SERIALS_PER_CALL,
+            format!("At most {} serials can be registered per call", MAX_SERIALS_PER_CALL)
+        );
+        require!(
+            batch.serials_registered + serial_hashes.len() as u128 <= batch.quantity,
+            "Batch has fewer units than registered serials"
+        );
+        let initial_storage = env::storage_usage();
+
+        for serial in &serial_hashes {
+            require!(!serial.is_empty(), "Serial hash must not be empty");
+            require!(!self.serials.contains_key(serial), format!("Serial {} is already registered", serial));
+            self.serials.insert(serial.clone(), SerialRecord { batch_id, order_id: None });
+        }
+        self.batches.get_mut(&batch_id).unwrap().serials_registered += serial_hashes.len() as u128;
+        MediEvent::SerialsRegistered {
+            batch_id,
+            count: serial_hashes.len() as u32,
+        }
+        .emit();
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
+
+    // Called by the seller as the packs are handed over, with the hashes
+    // scanned from them. Each pack must come from a batch the order was
+    // filled from, and no batch can give more packs than units it supplied.
+    pub fn dispense_serials(&mut self, order_id: u32, serial_hashes: Vec<String>) {
+        let order = self.orders.get(&order_id).expect("Order not found");
+        match order.pharmacy_id {
+            Some(pharmacy_id) => require!(
+                self.caller_pharmacy_id() == pharmacy_id,
+                "Only the seller can dispense the order's packs"
+            ),
+            None => self.assert_role(&[Role::Admin]),
+        }
+        require!(order.status != OrderStatus::Failed, "Order has failed");
+        require!(
+            order.serials.len() + serial_hashes.len() <= order.quantity as usize,
+            "More packs than the order's quantity"
+        );
+        // Units of each batch not yet matched to a scanned pack
+        let mut unmatched = order.batches.clone();
+        for serial in &order.serials {
+            let batch_id = self.serials[serial].batch_id;
+            if let Some((_, units)) = unmatched.iter_mut().find(|(id, _)| *id == batch_id) {
+                *units -= 1;
+            }
+        }
+        let initial_storage = env::storage_usage();
+
+        for serial in &serial_hashes {
+            let record = self
+                .serials
+                .get_mut(serial)
+                .unwrap_or_else(|| env::panic_str(&format!("Serial {} is not registered", serial)));
+            require!(record.order_id.is_none(), format!("Serial {} has already been dispensed", serial));
+            let units = unmatched
+                .iter_mut()
+                .find(|(batch_id, units)| *batch_id == record.batch_id && *units > 0)
+                .map(|(_, units)| units)
+                .unwrap_or_else(|| env::panic_str(&format!("Serial {} is not from a batch this order was filled from", serial)));
+            *units -= 1;
+            record.order_id = Some(order_id);
+        }
+
+        MediEvent::SerialsDispensed {
+            order_id,
+            count: serial_hashes.len() as u32,
+        }
+        .emit();
+        self.orders.get_mut(&order_id).unwrap().serials.extend(serial_hashes);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage);
+    }
+
+    // What a scanned pack's serial hash says about it. A recall outranks
+    // every other status.
+    pub fn verify_serial(&self, serial_hash: String) -> SerialStatus {
+        let Some(record) = self.serials.get(&serial_hash) else {
+            return SerialStatus::Unknown;
+        };
+        let batch = &self.batches[&record.batch_id];
+        if batch.recall_id.is_some() || self.drugs[&batch.medicine_id].recall_id.is_some() {
+            SerialStatus::Recalled
+        } else if record.order_id.is_some() {
+            SerialStatus::AlreadyDispensed
+        } else {
+            SerialStatus::Genuine
+        }
+    }
+
+    pub fn get_serial(&self, serial_hash: String) -> Option<SerialRecord> {
+        self.serials.get(&serial_hash).cloned()
+    }
+}
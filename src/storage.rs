@@ -0,0 +1,200 @@
+use crate::*;
+use near_sdk::json_types::U128;
+
+// Bytes reserved for an account's own entry in `storage_accounts`. Charged on
+// registration and the minimum balance an account must keep.
+pub const ACCOUNT_STORAGE_BYTES: u64 = 200;
+
+#[near(serializers = [json])]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[near(serializers = [json])]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+#[near(serializers = [borsh])]
+pub struct StorageAccount {
+    deposit: u128,
+    used_bytes: u64,
+}
+
+impl StorageAccount {
+    fn locked(&self) -> u128 {
+        self.used_bytes as u128 * env::storage_byte_cost().as_yoctonear()
+    }
+
+    fn balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: U128(self.deposit),
+            available: U128(self.deposit - self.locked()),
+        }
+    }
+}
+
+fn min_storage_balance() -> u128 {
+    ACCOUNT_STORAGE_BYTES as u128 * env::storage_byte_cost().as_yoctonear()
+}
+
+impl Contract {
+    // Persists pending collection writes so `env::storage_usage` reflects them
+    pub(crate) fn flush_collections(&mut self) {
+        self.roles.flush();
+        self.patients.flush();
+        self.patient_by_account.flush();
+        self.doctors.flush();
+        self.doctor_by_account.flush();
+        self.drugs.flush();
+        self.prescriptions.flush();
+        self.appointments.flush();
+        self.notifications.flush();
+        self.orders.flush();
+        self.messages.flush();
+        self.consents.flush();
+        self.medical_histories.flush();
+        self.medical_records.flush();
+        self.encryption_keys.flush();
+        self.encrypted_profiles.flush();
+        self.calendars.flush();
+        self.booked_slots.flush();
+        self.reviews.flush();
+        self.reviews_by_doctor.flush();
+        self.pharmacies.flush();
+        self.pharmacy_by_account.flush();
+        self.pharmacy_stock.flush();
+        self.pharmacy_medicines.flush();
+        self.stockists.flush();
+        self.batches.flush();
+        self.stock_batches.flush();
+        self.batch_by_lot.flush();
+        self.recalls.flush();
+        self.orders_by_medicine.flush();
+        self.provenance.flush();
+        self.serials.flush();
+        self.orders_by_patient.flush();
+        self.prescriptions_by_patient.flush();
+        self.appointments_by_patient.flush();
+        self.appointments_by_doctor.flush();
+        self.conversations.flush();
+        self.audit_log.flush();
+        self.audit_by_patient.flush();
+        self.audit_by_actor.flush();
+    }
+
+    // Bills `account_id` for the bytes written since `initial_usage` was taken
+    // and rejects the call if its storage deposit cannot cover them.
+    pub(crate) fn charge_storage(&mut self, account_id: &AccountId, initial_usage: u64) {
+        self.flush_collections();
+        let usage = env::storage_usage();
+        let account = self
+            .storage_accounts
+            .get_mut(account_id)
+            .expect("Account is not registered, call storage_deposit first");
+        if usage >= initial_usage {
+            account.used_bytes += usage - initial_usage;
+        } else {
+            account.used_bytes = account.used_bytes.saturating_sub(initial_usage - usage);
+        }
+        require!(
+            account.deposit >= account.locked(),
+            format!(
+                "Not enough storage deposit: {} bytes need {} yoctoNEAR",
+                account.used_bytes,
+                account.locked()
+            )
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    #[payable]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let mut deposit = env::attached_deposit().as_yoctonear();
+
+        if let Some(account) = self.storage_accounts.get_mut(&account_id) {
+            if registration_only.unwrap_or(false) {
+                self.refund_excess(&env::predecessor_account_id(), deposit, 0);
+            } else {
+                account.deposit += deposit;
+            }
+        } else {
+            let min = min_storage_balance();
+            require!(deposit >= min, format!("The attached deposit is less than the minimum storage balance of {}", min));
+            if registration_only.unwrap_or(false) {
+                self.refund_excess(&env::predecessor_account_id(), deposit, min);
+                deposit = min;
+            }
+            self.storage_accounts.insert(
+                account_id.clone(),
+                StorageAccount {
+                    deposit,
+                    used_bytes: ACCOUNT_STORAGE_BYTES,
+                },
+            );
+        }
+
+        self.storage_accounts[&account_id].balance()
+    }
+
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        near_sdk::assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let account = self
+            .storage_accounts
+            .get_mut(&account_id)
+            .expect("Account is not registered");
+        let available = account.deposit - account.locked();
+        let amount = amount.map_or(available, |amount| amount.0);
+        require!(amount <= available, "The amount is greater than the available storage balance");
+
+        account.deposit -= amount;
+        let balance = account.balance();
+        if amount > 0 {
+            Promise::new(account_id).transfer(NearToken::from_yoctonear(amount + 1));
+        }
+        balance
+    }
+
+    // Records are never deleted, so only accounts that own no records beyond
+    // their registration can leave; `force` is not supported.
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        near_sdk::assert_one_yocto();
+        require!(!force.unwrap_or(false), "Forced unregistration is not supported");
+        let account_id = env::predecessor_account_id();
+        let Some(account) = self.storage_accounts.get(&account_id) else {
+            return false;
+        };
+        require!(
+            account.used_bytes <= ACCOUNT_STORAGE_BYTES,
+            "Cannot unregister an account that still owns records"
+        );
+
+        let refund = account.deposit;
+        self.storage_accounts.remove(&account_id);
+        Promise::new(account_id).transfer(NearToken::from_yoctonear(refund + 1));
+        true
+    }
+
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(&account_id).map(StorageAccount::balance)
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(min_storage_balance()),
+            max: None,
+        }
+    }
+}
@@ -2,102 +2,1702 @@
  * The rest of this file holds the inline tests for the code above
  * Learn more about Rust tests: https://doc.rust-lang.org/book/ch11-01-writing-tests.html
  */
- #[cfg(test)]
- mod tests {
-    //  use near_sdk::AccountId;
- 
-    //  use crate::{Contract, DoctorInput};
- 
-    //  fn get_contract() -> Contract {
-    //      let bob: AccountId = "bob.near".parse().unwrap();
-    //      Contract::init(bob, None)
-    //  }
-    use super::*; // Import everything from the parent module
-    use near_workspaces::Contract;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::testing_env;
-    
-
-    fn get_contract() -> Contract {
-        let mut context = VMContextBuilder::new();
-        context.predecessor_account_id(accounts(0));
-        testing_env!(context.build());
-        Contract::default() // Initialize your contract
+use super::*; // Import everything from the parent module
+use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+use near_sdk::testing_env;
+
+fn owner() -> AccountId {
+    "medinear.testnet".parse().unwrap()
+}
+
+fn set_context(predecessor: AccountId) {
+    set_context_with_deposit(predecessor, 0);
+}
+
+fn set_context_with_deposit(predecessor: AccountId, deposit: u128) {
+    let mut context = VMContextBuilder::new();
+    context.current_account_id(owner());
+    context.predecessor_account_id(predecessor);
+    context.attached_deposit(NearToken::from_yoctonear(deposit));
+    testing_env!(context.build());
+}
+
+fn get_contract() -> Contract {
+    set_context(accounts(0));
+    Contract::default() // Initialize your contract
+}
+
+fn doctor_input(account_id: AccountId) -> DoctorInput {
+    DoctorInput {
+        title: "Dr.".to_string(),
+        first_name: "John".to_string(),
+        last_name: "Doe".to_string(),
+        gender: "Male".to_string(),
+        designation: "Cardiologist".to_string(),
+        last_work: "Hospital A".to_string(),
+        email: "john.doe@example.com".to_string(),
+        college_name: "Medical College".to_string(),
+        college_id: "MC123".to_string(),
+        joining_year: "2010".to_string(),
+        end_year: "2015".to_string(),
+        specialization: "Heart Surgery".to_string(),
+        registration_id: "REG123".to_string(),
+        college_address: "Meru 567".to_string(),
+        account_id,
+        profile_pic: "profile.jpg".to_string(),
+        bio: "Experienced doctor in cardiology.".to_string(),
+    }
+}
+
+fn patient_input(account_id: AccountId) -> PatientInput {
+    PatientInput {
+        title: "Ms.".to_string(),
+        first_name: "Jane".to_string(),
+        last_name: "Roe".to_string(),
+        gender: "Female".to_string(),
+        condition: "Fever".to_string(),
+        phone: "0700000000".to_string(),
+        email: "jane.roe@example.com".to_string(),
+        dob: "1990-01-01".to_string(),
+        city: "Meru".to_string(),
+        address: "Meru 123".to_string(),
+        doctor: "Dr. John Doe".to_string(),
+        profile_pic: "profile.jpg".to_string(),
+        account_id,
+        message: "Hello".to_string(),
+    }
+}
+
+const FEE: u128 = 42_000_000_000;
+const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
+
+fn register_storage(contract: &mut Contract, account_id: AccountId) {
+    set_context_with_deposit(account_id, ONE_NEAR);
+    contract.storage_deposit(None, None);
+}
+
+fn add_doctor_to_contract(contract: &mut Contract) {
+    for account_id in [accounts(1), accounts(2)] {
+        register_storage(contract, account_id.clone());
+        set_context_with_deposit(account_id.clone(), FEE);
+        contract.add_doctor(doctor_input(account_id));
+    }
+}
+
+fn add_patient_to_contract(contract: &mut Contract, account_id: AccountId) {
+    register_storage(contract, account_id.clone());
+    set_context_with_deposit(account_id.clone(), FEE);
+    contract.add_patient(patient_input(account_id));
+}
+
+#[test]
+fn get_doctor_by_id() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+
+    let doctor = contract.get_doctor_details(1).unwrap();
+
+    assert_eq!(doctor.id, 1);
+    assert_eq!(doctor.account_id, accounts(2));
+}
+
+#[test]
+fn profiles_resolve_by_account() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(3));
+
+    assert_eq!(contract.get_patient_by_account(accounts(3)).unwrap().id, 0);
+    assert_eq!(contract.get_doctor_by_account(accounts(2)).unwrap().id, 1);
+    assert!(contract.get_patient_by_account(accounts(2)).is_none());
+}
+
+#[test]
+#[should_panic(expected = "Account already has a patient profile")]
+fn one_patient_profile_per_account() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_patient_to_contract(&mut contract, accounts(3));
+}
+
+#[test]
+fn list_views_are_paginated() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+    for index in 3..6 {
+        add_patient_to_contract(&mut contract, accounts(index));
+    }
+
+    assert_eq!(contract.get_patients_count(), 3);
+    let page: Vec<u32> = contract.get_all_registered_patients(Some(1), Some(1)).iter().map(|p| p.id).collect();
+    assert_eq!(page, vec![1]);
+    assert_eq!(contract.get_all_registered_patients(Some(2), Some(10)).len(), 1);
+    assert!(contract.get_all_registered_patients(Some(3), None).is_empty());
+    assert_eq!(contract.get_all_doctors_data(None, Some(1))[0].id, 0);
+    assert_eq!(contract.get_doctors_count(), 2);
+}
+
+// Parses the NEP-297 events logged by the last call
+fn logged_events() -> Vec<near_sdk::serde_json::Value> {
+    get_logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|json| near_sdk::serde_json::from_str(json).unwrap())
+        .collect()
+}
+
+#[test]
+fn state_changes_emit_nep297_events() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+
+    let events = logged_events();
+    assert_eq!(events[0]["standard"], "medi");
+    assert_eq!(events[0]["version"], "1.0.0");
+    assert_eq!(events[0]["event"], "role_granted");
+    assert_eq!(events[1]["event"], "patient_registered");
+    assert_eq!(events[1]["data"]["patient_id"], 0);
+    assert_eq!(events[1]["data"]["account_id"], accounts(3).to_string());
+
+    set_context(owner());
+    contract.update_registration_fee(5);
+    let events = logged_events();
+    assert_eq!(events[0]["event"], "fee_updated");
+    assert_eq!(events[0]["data"]["fee"], "registration");
+    assert_eq!(events[0]["data"]["amount"], "5");
+}
+
+#[test]
+fn approve_doctor_updates_in_place() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+
+    set_context(owner());
+    contract.approve_doctor(0);
+
+    assert_eq!(contract.get_all_doctors_data(None, None).len(), 2);
+    assert!(contract.get_doctor_details(0).unwrap().is_approved);
+    assert_eq!(contract.get_approved_doctors(None, None).len(), 1);
+}
+
+// Texts of the patient's current history entries, read as the current caller
+fn history_texts(contract: &mut Contract, patient_id: u32) -> Vec<String> {
+    contract
+        .get_patient_medical_history(patient_id, None, None, None, None, None)
+        .into_iter()
+        .map(|entry| entry.text)
+        .collect()
+}
+
+#[test]
+fn update_patient_medical_updates_in_place() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_patient_to_contract(&mut contract, accounts(4));
+    set_context(owner());
+    contract.approve_doctor(0);
+    set_context(accounts(4));
+    contract.grant_consent(0, true, true, None, None);
+
+    set_context(accounts(1));
+    contract.update_patient_medical(1, "Malaria".to_string());
+    contract.update_patient_medical(1, "Recovered".to_string());
+
+    assert_eq!(contract.get_all_registered_patients(None, None).len(), 2);
+    assert_eq!(history_texts(&mut contract, 1), vec!["Malaria", "Recovered"]);
+    set_context(accounts(3));
+    assert!(history_texts(&mut contract, 0).is_empty());
+}
+
+#[test]
+fn amended_records_are_superseded() {
+    let mut contract = get_contract();
+    let appointment_id = book_appointment_in_contract(&mut contract);
+    set_context(accounts(3));
+    contract.grant_consent(1, true, true, None, None);
+
+    set_context(accounts(2));
+    let allergy = contract.add_medical_record(0, RecordKind::Allergy, "Penicilin".to_string(), Some(appointment_id), None, None);
+    contract.add_medical_record(0, RecordKind::Diagnosis, "Malaria".to_string(), None, Some(vec!["bafy-lab".to_string()]), None);
+    let amended = contract.amend_medical_record(allergy, "Penicillin".to_string(), None, None);
+
+    let allergies = contract.get_patient_medical_history(0, Some(RecordKind::Allergy), None, None, None, None);
+    assert_eq!(allergies.len(), 1);
+    assert_eq!(allergies[0].id, amended);
+    assert_eq!(allergies[0].supersedes, Some(allergy));
+    assert_eq!(allergies[0].appointment_id, Some(appointment_id));
+    assert_eq!(contract.get_medical_record(allergy).superseded_by, Some(amended));
+    assert_eq!(history_texts(&mut contract, 0), vec!["Malaria", "Penicillin"]);
+    assert!(contract.get_patient_medical_history(0, None, Some(1), None, None, None).is_empty());
+}
+
+#[test]
+fn medical_records_are_billed_to_the_writing_doctor() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    set_context(accounts(3));
+    contract.grant_consent(1, true, true, None, None);
+
+    set_context(accounts(2));
+    let available = contract.storage_balance_of(accounts(2)).unwrap().available.0;
+    let entry_id = contract.add_medical_record(0, RecordKind::Diagnosis, "Malaria".to_string(), None, None, None);
+    let after_add = contract.storage_balance_of(accounts(2)).unwrap().available.0;
+    assert!(after_add < available);
+    contract.amend_medical_record(entry_id, "Typhoid".to_string(), None, None);
+    assert!(contract.storage_balance_of(accounts(2)).unwrap().available.0 < after_add);
+}
+
+#[test]
+#[should_panic(expected = "Record text is limited to 10000 bytes")]
+fn oversized_medical_records_are_rejected() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    set_context(accounts(3));
+    contract.grant_consent(1, true, true, None, None);
+
+    set_context(accounts(2));
+    contract.add_medical_record(0, RecordKind::Note, "x".repeat(records::MAX_RECORD_TEXT_LENGTH + 1), None, None, None);
+}
+
+fn wrapped_key(account_id: AccountId, public_key: &str) -> WrappedKey {
+    WrappedKey {
+        account_id,
+        public_key: public_key.to_string(),
+        wrapped_key: format!("key-for-{}", public_key),
+    }
+}
+
+#[test]
+fn encrypted_records_are_rewrapped_for_new_doctors() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    set_context(owner());
+    contract.approve_doctor(0);
+    for (account_id, public_key) in [(accounts(1), "pk-d0"), (accounts(2), "pk-d1"), (accounts(3), "pk-p")] {
+        set_context(account_id);
+        contract.set_encryption_key(public_key.to_string());
+    }
+    set_context(accounts(3));
+    contract.grant_consent(1, true, true, None, None);
+
+    set_context(accounts(2));
+    let payload = EncryptedPayload {
+        ciphertext: "c1ph3r".to_string(),
+        wrapped_keys: vec![wrapped_key(accounts(3), "pk-p"), wrapped_key(accounts(2), "pk-d1")],
+    };
+    let entry_id = contract.add_medical_record(0, RecordKind::Diagnosis, String::new(), None, None, Some(payload));
+
+    assert_eq!(contract.get_unshared_encrypted_records(0, 0), vec![Some(entry_id)]);
+    set_context(accounts(3));
+    let keys = vec![RewrappedKey {
+        entry_id: Some(entry_id),
+        wrapped_key: "key-for-pk-d0".to_string(),
+    }];
+    contract.grant_consent(0, true, false, None, Some(keys));
+    assert!(contract.get_unshared_encrypted_records(0, 0).is_empty());
+
+    set_context(accounts(1));
+    let entry = contract.get_medical_record(entry_id);
+    let readers: Vec<AccountId> = entry.encrypted.unwrap().wrapped_keys.into_iter().map(|key| key.account_id).collect();
+    assert_eq!(readers, vec![accounts(3), accounts(2), accounts(1)]);
+
+    set_context(accounts(3));
+    contract.revoke_consent(0);
+    assert_eq!(contract.get_unshared_encrypted_records(0, 0), vec![Some(entry_id)]);
+}
+
+#[test]
+#[should_panic(expected = "Encrypted payload must be readable by the patient")]
+fn encrypted_records_must_be_readable_by_the_patient() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    set_context(accounts(2));
+    contract.set_encryption_key("pk-d1".to_string());
+    set_context(accounts(3));
+    contract.grant_consent(1, true, true, None, None);
+
+    set_context(accounts(2));
+    let payload = EncryptedPayload {
+        ciphertext: "c1ph3r".to_string(),
+        wrapped_keys: vec![wrapped_key(accounts(2), "pk-d1")],
+    };
+    contract.add_medical_record(0, RecordKind::Diagnosis, String::new(), None, None, Some(payload));
+}
+
+#[test]
+#[should_panic(expected = "Caller has no Write consent for patient 0")]
+fn unapproved_doctor_cannot_update_medical_history() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(3));
+    set_context(accounts(3));
+    contract.grant_consent(0, true, true, None, None);
+
+    set_context(accounts(1));
+    contract.update_patient_medical(0, "Malaria".to_string());
+}
+
+#[test]
+fn consent_controls_medical_history_access() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(3));
+    set_context(owner());
+    contract.approve_doctor(0);
+
+    set_context(accounts(3));
+    contract.grant_consent(0, true, false, None, None);
+    assert!(contract.has_consent(0, 0, Access::Read));
+    assert!(!contract.has_consent(0, 0, Access::Write));
+    set_context(accounts(1));
+    assert!(history_texts(&mut contract, 0).is_empty());
+
+    set_context(accounts(3));
+    contract.revoke_consent(0);
+    assert!(!contract.has_consent(0, 0, Access::Read));
+    assert!(contract.get_consents(0).is_empty());
+}
+
+#[test]
+fn record_access_is_audited() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(3));
+    set_context(owner());
+    contract.approve_doctor(0);
+    set_context(accounts(3));
+    contract.grant_consent(0, true, true, None, None);
+    set_context(accounts(1));
+    contract.update_patient_medical(0, "Malaria".to_string());
+    let available = contract.storage_balance_of(accounts(1)).unwrap().available.0;
+    history_texts(&mut contract, 0);
+    // The reader pays for the audit entry its read leaves behind, unless it
+    // is the patient reading their own record
+    assert!(contract.storage_balance_of(accounts(1)).unwrap().available.0 < available);
+    set_context(accounts(3));
+    let available = contract.storage_balance_of(accounts(3)).unwrap().available.0;
+    history_texts(&mut contract, 0);
+    assert_eq!(contract.storage_balance_of(accounts(3)).unwrap().available.0, available);
+
+    let actions: Vec<AuditAction> = contract
+        .get_patient_audit_log(0, None, None)
+        .iter()
+        .map(|entry| entry.action)
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            AuditAction::ConsentGranted,
+            AuditAction::MedicalHistoryUpdated,
+            AuditAction::MedicalHistoryRead,
+            AuditAction::MedicalHistoryRead,
+        ]
+    );
+    assert_eq!(contract.get_actor_audit_log(accounts(1), Some(1), Some(1))[0].action, AuditAction::MedicalHistoryRead);
+    assert_eq!(contract.get_actor_audit_log(owner(), None, None)[0].action, AuditAction::DoctorApproved);
+    assert_eq!(contract.get_audit_log_count(), 5);
+}
+
+#[test]
+#[should_panic(expected = "Caller has no Read consent for patient 0")]
+fn expired_consent_is_rejected() {
+    let mut contract = get_contract();
+    add_doctor_to_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(3));
+    set_context(owner());
+    contract.approve_doctor(0);
+    set_context(accounts(3));
+    contract.grant_consent(0, true, true, Some(1_000), None);
+
+    set_context_at(accounts(1), 1_000);
+    history_texts(&mut contract, 0);
+}
+
+#[test]
+fn roles_are_granted_and_revoked() {
+    set_context(owner());
+    let mut contract = Contract::init(owner(), Some(vec![accounts(5)]));
+    assert_eq!(contract.get_role_members(Role::Admin, None, None), vec![accounts(5)]);
+
+    // Admins manage non-admin roles and pass admin guards
+    set_context(accounts(5));
+    contract.grant_role(accounts(1), Role::Pharmacist);
+    contract.update_appointment_fee(7);
+    assert!(contract.has_role(accounts(1), Role::Pharmacist));
+    assert_eq!(contract.get_appointment_fee(), 7);
+
+    contract.revoke_role(accounts(1), Role::Pharmacist);
+    assert!(contract.get_roles(accounts(1)).is_empty());
+    assert_eq!(contract.get_roles(owner()), vec![Role::Owner]);
+}
+
+#[test]
+#[should_panic(expected = "Only the owner can call this method")]
+fn admin_cannot_grant_admin() {
+    set_context(owner());
+    let mut contract = Contract::init(owner(), Some(vec![accounts(5)]));
+
+    set_context(accounts(5));
+    contract.grant_role(accounts(1), Role::Admin);
+}
+
+#[test]
+#[should_panic(expected = "Only accounts with one of the roles [Admin, Pharmacist] can call this method")]
+fn patient_cannot_add_medicine() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    assert!(contract.has_role(accounts(3), Role::Patient));
+
+    set_context(accounts(3));
+    contract.add_medicine(
+        0,
+        "Paracetamol".to_string(),
+        "Panadol".to_string(),
+        "GSK".to_string(),
+        "2024-01-01".to_string(),
+        "2026-01-01".to_string(),
+        "info@gsk.com".to_string(),
+        0,
+        "London".to_string(),
+        100,
+        5,
+        "Nairobi".to_string(),
+        254700000000,
+        "panadol.png".to_string(),
+        "Pain relief".to_string(),
+        None,
+        None,
+    );
+}
+
+#[test]
+fn storage_is_charged_to_the_writer() {
+    let mut contract = get_contract();
+    register_storage(&mut contract, accounts(3));
+    let before = contract.storage_balance_of(accounts(3)).unwrap();
+    assert_eq!(before.total.0, ONE_NEAR);
+
+    set_context_with_deposit(accounts(3), FEE);
+    contract.add_patient(patient_input(accounts(3)));
+    let after = contract.storage_balance_of(accounts(3)).unwrap();
+    assert!(after.available.0 < before.available.0);
+
+    set_context_with_deposit(accounts(3), 1);
+    let withdrawn = contract.storage_withdraw(None);
+    assert_eq!(withdrawn.available.0, 0);
+    assert_eq!(withdrawn.total.0, ONE_NEAR - after.available.0);
+}
+
+#[test]
+#[should_panic(expected = "Not enough storage deposit")]
+fn writes_beyond_storage_deposit_are_rejected() {
+    let mut contract = get_contract();
+    let min = contract.storage_balance_bounds().min.0;
+    set_context_with_deposit(accounts(3), min);
+    contract.storage_deposit(None, Some(true));
+
+    set_context_with_deposit(accounts(3), FEE);
+    contract.add_patient(patient_input(accounts(3)));
+}
+
+#[test]
+#[should_panic(expected = "Account is not registered, call storage_deposit first")]
+fn unregistered_accounts_cannot_write() {
+    let mut contract = get_contract();
+    set_context_with_deposit(accounts(3), FEE);
+    contract.add_patient(patient_input(accounts(3)));
+}
+
+// A 30 minute slot `date` days after the epoch
+fn slot(date: u32, start_minute: u32) -> Slot {
+    Slot {
+        date,
+        start_minute,
+        end_minute: start_minute + 30,
+    }
+}
+
+// Registers doctors 0 and 1 (doctor 1 approved, available 09:00-12:00 every
+// day) and patient 0 (accounts(3)), then books appointment 0 with doctor 1 on
+// day 1 at 09:00 as the patient.
+fn book_appointment_in_contract(contract: &mut Contract) -> u32 {
+    add_doctor_to_contract(contract);
+    add_patient_to_contract(contract, accounts(3));
+    set_context(owner());
+    contract.approve_doctor(1);
+    set_context(accounts(2));
+    let windows = (0..7)
+        .map(|weekday| AvailabilityWindow {
+            weekday,
+            start_minute: 9 * 60,
+            end_minute: 12 * 60,
+        })
+        .collect();
+    contract.set_weekly_availability(30, windows);
+
+    set_context_with_deposit(accounts(3), FEE);
+    contract.book_appointment(
+        0,
+        1,
+        slot(1, 9 * 60),
+        "Fever".to_string(),
+        "Check up".to_string(),
+        None,
+    )
+}
+
+#[test]
+fn booking_can_share_medical_history() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    assert!(!contract.has_consent(0, 1, Access::Read));
+
+    set_context_with_deposit(accounts(3), FEE);
+    contract.book_appointment(
+        0,
+        1,
+        slot(2, 10 * 60),
+        "Fever".to_string(),
+        "Follow up".to_string(),
+        Some(true),
+    );
+    assert!(contract.has_consent(0, 1, Access::Write));
+
+    set_context_at(accounts(3), consent::APPOINTMENT_CONSENT_DURATION);
+    assert!(!contract.has_consent(0, 1, Access::Read));
+}
+
+#[test]
+fn appointment_lifecycle_is_persisted() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context(accounts(2));
+    contract.confirm_appointment(id);
+    set_context(accounts(3));
+    contract.check_in_appointment(id);
+    assert_eq!(contract.get_patient_appointment(0, None, None).len(), 1);
+
+    set_context(accounts(2));
+    contract.complete_appointment(id);
+
+    assert_eq!(contract.get_all_appointments(None, None).len(), 1);
+    assert!(contract.get_patient_appointment(0, None, None).is_empty());
+    let history = contract.get_doctor_appointment_historys(1, None, None);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].status, AppointmentStatus::Completed);
+    let steps: Vec<AppointmentStatus> = history[0].history.iter().map(|change| change.status).collect();
+    assert_eq!(
+        steps,
+        vec![
+            AppointmentStatus::Requested,
+            AppointmentStatus::Confirmed,
+            AppointmentStatus::CheckedIn,
+            AppointmentStatus::Completed,
+        ]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Cannot move appointment from Requested to Completed")]
+fn appointment_cannot_skip_steps() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context(accounts(2));
+    contract.complete_appointment(id);
+}
+
+#[test]
+#[should_panic(expected = "Caller may not move appointment 0 to Confirmed")]
+fn patient_cannot_confirm_appointment() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    contract.confirm_appointment(id);
+}
+
+#[test]
+fn booked_slots_are_no_longer_available() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    let slots = contract.get_available_slots(1, 1, 2);
+    assert_eq!(slots.len(), 11);
+    assert_eq!(slots[0], slot(1, 9 * 60 + 30));
+
+    set_context(accounts(2));
+    contract.add_availability_exception(2, 2, "Leave".to_string());
+    assert_eq!(contract.get_available_slots(1, 1, 2).len(), 5);
+
+    set_context(accounts(3));
+    contract.cancel_appointment(id);
+    assert_eq!(contract.get_available_slots(1, 1, 2)[0], slot(1, 9 * 60));
+    assert!(contract.get_available_slots(0, 1, 2).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Slot is already booked")]
+fn overlapping_bookings_are_rejected() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(4));
+
+    set_context_with_deposit(accounts(4), FEE);
+    contract.book_appointment(1, 1, slot(1, 9 * 60), "Cough".to_string(), "Check up".to_string(), None);
+}
+
+#[test]
+#[should_panic(expected = "Slot is not offered by the doctor")]
+fn bookings_must_match_a_derived_slot() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+
+    set_context_with_deposit(accounts(3), FEE);
+    contract.book_appointment(0, 1, slot(1, 9 * 60 + 15), "Fever".to_string(), "Check up".to_string(), None);
+}
+
+#[test]
+fn reschedule_links_appointments() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    let new_id = contract.reschedule_appointment(id, slot(2, 10 * 60));
+
+    let previous = contract.get_patient_appointment_history(0, None, None);
+    assert_eq!(previous.len(), 1);
+    assert_eq!(previous[0].status, AppointmentStatus::Rescheduled);
+    assert_eq!(previous[0].rescheduled_to, Some(new_id));
+    let current = contract.get_doctor_appointments(1, None, None);
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0].rescheduled_from, Some(id));
+}
+
+fn set_context_at(predecessor: AccountId, block_timestamp: u64) {
+    let mut context = VMContextBuilder::new();
+    context.current_account_id(owner());
+    context.predecessor_account_id(predecessor);
+    context.block_timestamp(block_timestamp);
+    testing_env!(context.build());
+}
+
+const HOUR: u64 = 3_600 * 1_000_000_000;
+
+#[test]
+fn early_cancellation_is_refunded_in_full() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context(accounts(3));
+    contract.cancel_appointment(id);
+
+    let appointment = &contract.get_patient_appointment_history(0, None, None)[0];
+    assert_eq!((appointment.fee_paid, appointment.fee_refunded), (0, FEE));
+    assert_eq!(contract.get_fees_collected().appointment_fees, 0);
+    assert!(logged_events().iter().any(|event| event["event"] == "appointment_refunded"));
+}
+
+#[test]
+fn late_cancellation_follows_the_policy() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+    set_context(owner());
+    contract.update_cancellation_policy(CancellationPolicy {
+        full_refund_hours: 12,
+        late_refund_percent: 25,
+    });
+
+    // Two hours before the 09:00 slot on day 1
+    set_context_at(accounts(3), 24 * HOUR + 7 * HOUR);
+    contract.cancel_appointment(id);
+
+    let appointment = &contract.get_patient_appointment_history(0, None, None)[0];
+    assert_eq!(appointment.fee_refunded, FEE / 4);
+    // The rest of the escrow is released to the doctor, less commission
+    assert_eq!(contract.get_fees_collected().appointment_fees, (FEE - FEE / 4) / 10);
+    assert_eq!(contract.get_doctor_details(1).unwrap().earnings, (FEE - FEE / 4) / 10 * 9);
+}
+
+#[test]
+fn doctor_cancellation_is_refunded_in_full() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context_at(accounts(2), 24 * HOUR + 8 * HOUR);
+    contract.cancel_appointment(id);
+
+    assert_eq!(contract.get_patient_appointment_history(0, None, None)[0].fee_refunded, FEE);
+}
+
+#[test]
+fn no_shows_are_not_refunded() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context(accounts(2));
+    contract.confirm_appointment(id);
+    // Ten minutes into the 09:00 slot on day 1
+    set_context_at(accounts(2), 33 * HOUR + HOUR / 6);
+    contract.mark_no_show(id);
+
+    let appointment = &contract.get_patient_appointment_history(0, None, None)[0];
+    assert_eq!((appointment.fee_paid, appointment.fee_refunded), (FEE, 0));
+}
+
+#[test]
+#[should_panic(expected = "A no-show can only be recorded once the slot has started")]
+fn no_shows_are_not_recorded_early() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context(accounts(2));
+    contract.confirm_appointment(id);
+    contract.mark_no_show(id);
+}
+
+#[test]
+#[should_panic(expected = "Appointments cannot be cancelled by the patient once they have started")]
+fn patients_cannot_cancel_started_appointments() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+    set_context(accounts(2));
+    contract.confirm_appointment(id);
+
+    set_context_at(accounts(3), 33 * HOUR);
+    contract.cancel_appointment(id);
+}
+
+#[test]
+fn unconfirmed_started_appointments_are_refunded_in_full() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context_at(accounts(3), 33 * HOUR);
+    contract.cancel_appointment(id);
+
+    assert_eq!(contract.get_patient_appointment_history(0, None, None)[0].fee_refunded, FEE);
+    assert_eq!(contract.get_escrow_balance(id), 0);
+}
+
+#[test]
+fn confirmed_appointments_never_checked_in_time_out() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+    set_context(accounts(2));
+    contract.confirm_appointment(id);
+
+    // A day after the 09:00-09:30 slot on day 1 ended
+    set_context_at(accounts(3), 33 * HOUR + HOUR / 2 + CHECK_IN_TIMEOUT_HOURS * HOUR);
+    contract.cancel_appointment(id);
+
+    assert_eq!(contract.get_patient_appointment_history(0, None, None)[0].fee_refunded, FEE);
+    assert_eq!(contract.get_escrow_balance(id), 0);
+}
+
+#[test]
+#[should_panic(expected = "Appointments can only be rescheduled at least 24 hours ahead")]
+fn late_reschedule_is_rejected() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context_at(accounts(3), 24 * HOUR);
+    contract.reschedule_appointment(id, slot(2, 10 * 60));
+}
+
+#[test]
+fn fees_are_recorded_and_collected() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    assert_eq!(contract.get_patient_id(0).unwrap().registration_fee_paid, FEE);
+    assert_eq!(contract.get_doctor_details(1).unwrap().registration_fee_paid, FEE);
+    assert_eq!(contract.get_all_appointments(None, None)[0].fee_paid, FEE);
+    let treasury = contract.get_fees_collected();
+    assert_eq!(treasury.registration_fees, 3 * FEE);
+    assert_eq!(treasury.appointment_fees, 0);
+    assert_eq!(treasury.escrowed, FEE);
+
+    let new_id = contract.reschedule_appointment(id, slot(2, 10 * 60));
+    assert_eq!(contract.get_patient_appointment(0, None, None)[0].id, new_id);
+    assert_eq!(contract.get_patient_appointment(0, None, None)[0].fee_paid, FEE);
+    assert_eq!(contract.get_escrow_balance(id), 0);
+    assert_eq!(contract.get_escrow_balance(new_id), FEE);
+    assert_eq!(contract.get_fees_collected().total, 3 * FEE);
+}
+
+#[test]
+fn completed_appointments_release_escrow_to_the_doctor() {
+    let mut contract = get_contract();
+    let id = complete_appointment_in_contract(&mut contract);
+
+    assert_eq!(contract.get_escrow_balance(id), 0);
+    let treasury = contract.get_fees_collected();
+    assert_eq!((treasury.appointment_fees, treasury.escrowed), (FEE / 10, 0));
+    let doctor = contract.get_doctor_details(1).unwrap();
+    assert_eq!((doctor.earnings, doctor.total_earned), (FEE - FEE / 10, FEE - FEE / 10));
+
+    contract.withdraw_earnings(Some(FEE / 2));
+    assert_eq!(contract.get_doctor_details(1).unwrap().earnings, FEE / 2 - FEE / 10);
+
+    // A failed transfer puts the earnings back
+    set_context(owner());
+    assert!(!contract.on_earnings_withdrawn(1, FEE / 2, Err(PromiseError::Failed)));
+    let doctor = contract.get_doctor_details(1).unwrap();
+    assert_eq!((doctor.earnings, doctor.total_withdrawn), (FEE - FEE / 10, 0));
+}
+
+#[test]
+fn owner_withdraws_collected_fees() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+
+    set_context(owner());
+    contract.withdraw_fees(Some(FEE));
+    let treasury = contract.get_fees_collected();
+    assert_eq!((treasury.withdrawn, treasury.withdrawable), (FEE, 2 * FEE));
+
+    // A failed transfer makes the fees withdrawable again
+    assert!(!contract.on_fees_withdrawn(owner(), FEE, Err(PromiseError::Failed)));
+    contract.withdraw_fees(None);
+    let treasury = contract.get_fees_collected();
+    assert_eq!((treasury.withdrawn, treasury.withdrawable, treasury.escrowed), (3 * FEE, 0, FEE));
+}
+
+#[test]
+#[should_panic(expected = "Amount exceeds the withdrawable fees")]
+fn escrowed_fees_cannot_be_withdrawn() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    set_context(owner());
+    contract.withdraw_fees(Some(3 * FEE + 1));
+}
+
+// Books appointment 0 (see `book_appointment_in_contract`) and completes it
+fn complete_appointment_in_contract(contract: &mut Contract) -> u32 {
+    let id = book_appointment_in_contract(contract);
+    set_context(accounts(2));
+    contract.confirm_appointment(id);
+    contract.check_in_appointment(id);
+    contract.complete_appointment(id);
+    id
+}
+
+#[test]
+fn completed_appointments_can_be_rated() {
+    let mut contract = get_contract();
+    let id = complete_appointment_in_contract(&mut contract);
+
+    set_context(accounts(3));
+    contract.rate_appointment(id, 5, Some("Very thorough".to_string()));
+
+    let doctor = contract.get_doctor_details(1).unwrap();
+    assert_eq!((doctor.appointment_counts, doctor.successful_treaments), (1, 1));
+    assert_eq!((doctor.rating_sum, doctor.rating_count), (5, 1));
+    let reviews = contract.get_doctor_reviews(1, None, Some(10));
+    assert_eq!(reviews.len(), 1);
+    assert_eq!(reviews[0].review.as_deref(), Some("Very thorough"));
+
+    // Doctor 0 shares the specialization but has no ratings yet
+    set_context(owner());
+    contract.approve_doctor(0);
+    let top: Vec<u32> = contract
+        .get_top_doctors("heart surgery".to_string(), None, None)
+        .iter()
+        .map(|doctor| doctor.id)
+        .collect();
+    assert_eq!(top, vec![1, 0]);
+    assert!(contract.get_top_doctors("Dermatology".to_string(), None, None).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Appointment has already been rated")]
+fn appointments_are_rated_once() {
+    let mut contract = get_contract();
+    let id = complete_appointment_in_contract(&mut contract);
+
+    set_context(accounts(3));
+    contract.rate_appointment(id, 3, None);
+    contract.rate_appointment(id, 4, None);
+}
+
+#[test]
+#[should_panic(expected = "Only completed appointments can be rated")]
+fn open_appointments_cannot_be_rated() {
+    let mut contract = get_contract();
+    let id = book_appointment_in_contract(&mut contract);
+
+    set_context(accounts(3));
+    contract.rate_appointment(id, 5, None);
+}
+
+#[test]
+#[should_panic(expected = "Amount exceeds the withdrawable earnings")]
+fn doctors_cannot_withdraw_escrowed_fees() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+
+    set_context(accounts(2));
+    contract.withdraw_earnings(Some(1));
+}
+
+#[test]
+#[should_panic(expected = "Attached deposit must cover the fee of 42000000000")]
+fn registration_requires_fee() {
+    let mut contract = get_contract();
+    set_context_with_deposit(accounts(3), FEE - 1);
+    contract.add_patient(patient_input(accounts(3)));
+}
+
+// Adds medicine 0 priced at 100 with a 10% discount and 5 units in stock
+fn add_medicine_to_contract(contract: &mut Contract) {
+    set_context(owner());
+    contract.add_medicine(
+        0,
+        "Paracetamol".to_string(),
+        "Panadol".to_string(),
+        "GSK".to_string(),
+        "2024-01-01".to_string(),
+        "2026-01-01".to_string(),
+        "info@gsk.com".to_string(),
+        10,
+        "London".to_string(),
+        100,
+        5,
+        "Nairobi".to_string(),
+        254700000000,
+        "panadol.png".to_string(),
+        "Pain relief".to_string(),
+        None,
+        None,
+    );
+}
+
+fn prescription_input(medicine_id: u32, patient_id: u32) -> PrescriptionInput {
+    PrescriptionInput {
+        medicine_id,
+        patient_id,
+        dosage: "500mg".to_string(),
+        frequency: "Twice a day".to_string(),
+        duration_days: 5,
+        quantity: 2,
+        refills: 1,
+        expires_at: 30 * 24 * HOUR,
+        instructions: "Take after meals".to_string(),
+        appointment_id: Some(0),
+    }
+}
+
+#[test]
+fn prescriptions_are_numbered_and_signed_by_the_caller() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    add_medicine_to_contract(&mut contract);
+
+    set_context(accounts(2));
+    assert_eq!(contract.prescribe_medicine(prescription_input(0, 0)), 0);
+    let mut input = prescription_input(0, 0);
+    input.appointment_id = None;
+    assert_eq!(contract.prescribe_medicine(input), 1);
+
+    let prescription = contract.get_prescription(0).unwrap();
+    assert_eq!(prescription.doctor_id, 1);
+    assert_eq!(prescription.appointment_id, Some(0));
+    assert_eq!(prescription.dosage, "500mg");
+    assert_eq!(contract.get_patient_prescriptions(0, None, None).len(), 2);
+    assert!(contract.get_patient_prescriptions(1, None, None).is_empty());
+}
+
+// Marks medicine 0 prescription-only and has doctor 1 prescribe 2 units with
+// one refill to patient 0; returns the prescription id.
+fn prescribe_in_contract(contract: &mut Contract) -> u32 {
+    book_appointment_in_contract(contract);
+    add_medicine_to_contract(contract);
+    contract.update_medicine_schedule(0, true, None);
+    set_context(accounts(2));
+    contract.prescribe_medicine(prescription_input(0, 0))
+}
+
+#[test]
+fn party_views_list_only_their_records() {
+    let mut contract = get_contract();
+    prescribe_in_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(4));
+    set_context(accounts(2));
+    contract.prescribe_medicine(PrescriptionInput {
+        appointment_id: None,
+        ..prescription_input(0, 1)
+    });
+    contract.send_message(accounts(3), "Take it with food".to_string());
+    contract.send_message(accounts(4), "Come in on Monday".to_string());
+
+    let prescriptions = contract.get_patient_prescriptions(1, None, None);
+    assert_eq!(prescriptions.len(), 1);
+    assert_eq!(prescriptions[0].patient_id, 1);
+    assert_eq!(contract.get_patient_appointment(0, None, None).len(), 1);
+    assert!(contract.get_patient_appointment(1, None, None).is_empty());
+    assert_eq!(contract.get_doctor_appointments(1, None, None).len(), 1);
+    assert!(contract.get_doctor_appointments(0, None, None).is_empty());
+    let messages = contract.get_messages(accounts(4), accounts(2), None, None);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].message, "Come in on Monday");
+}
+
+#[test]
+fn prescription_only_medicine_consumes_fills() {
+    let mut contract = get_contract();
+    let id = prescribe_in_contract(&mut contract);
+
+    set_context_with_deposit(accounts(3), 180);
+    contract.buy_medicine(0, 2, 0, Some(id), None);
+    contract.buy_medicine(0, 2, 0, Some(id), None);
+    assert_eq!(contract.get_prescription(id).unwrap().fills_used, 2);
+    assert_eq!(contract.get_order(1).unwrap().prescription_id, Some(id));
+
+    // A failed payment gives the fill back
+    set_context(owner());
+    contract.on_order_payment(1, Err(PromiseError::Failed));
+    assert_eq!(contract.get_prescription(id).unwrap().fills_used, 1);
+}
+
+#[test]
+#[should_panic(expected = "Prescription has no refills left")]
+fn exhausted_prescriptions_are_rejected() {
+    let mut contract = get_contract();
+    let id = prescribe_in_contract(&mut contract);
+
+    set_context_with_deposit(accounts(3), 90);
+    for _ in 0..3 {
+        contract.buy_medicine(0, 1, 0, Some(id), None);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Quantity exceeds the 2 units prescribed")]
+fn purchases_are_capped_at_the_prescribed_quantity() {
+    let mut contract = get_contract();
+    let id = prescribe_in_contract(&mut contract);
+
+    set_context_with_deposit(accounts(3), 270);
+    contract.buy_medicine(0, 3, 0, Some(id), None);
+}
+
+#[test]
+#[should_panic(expected = "Medicine requires a prescription")]
+fn prescription_only_medicine_needs_a_prescription() {
+    let mut contract = get_contract();
+    prescribe_in_contract(&mut contract);
+
+    set_context_with_deposit(accounts(3), 90);
+    contract.buy_medicine(0, 1, 0, None, None);
+}
+
+#[test]
+#[should_panic(expected = "Prescription has expired")]
+fn expired_prescriptions_cannot_be_filled() {
+    let mut contract = get_contract();
+    let id = prescribe_in_contract(&mut contract);
+
+    set_context_at(accounts(3), 30 * 24 * HOUR);
+    contract.buy_medicine(0, 1, 0, Some(id), None);
+}
+
+#[test]
+#[should_panic(expected = "Medicine not found")]
+fn prescriptions_require_an_existing_medicine() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+
+    set_context(accounts(2));
+    contract.prescribe_medicine(prescription_input(0, 0));
+}
+
+#[test]
+#[should_panic(expected = "Appointment does not belong to this patient and doctor")]
+fn prescriptions_must_come_from_the_appointment_doctor() {
+    let mut contract = get_contract();
+    book_appointment_in_contract(&mut contract);
+    add_medicine_to_contract(&mut contract);
+    contract.approve_doctor(0);
+
+    set_context(accounts(1));
+    contract.prescribe_medicine(prescription_input(0, 0));
+}
+
+#[test]
+fn buy_medicine_records_order_and_updates_stock() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_medicine_to_contract(&mut contract);
+
+    set_context_with_deposit(accounts(3), 1_000);
+    contract.buy_medicine(0, 5, 0, None, None);
+
+    let medicine = contract.get_medicine_by_id(0).unwrap();
+    assert_eq!(medicine.quantity, 0);
+    assert!(!medicine.availability);
+    let order = contract.get_order(0).unwrap();
+    assert_eq!(order.payment_amount, 450);
+    assert_eq!(order.status, OrderStatus::Pending);
+
+    set_context(owner());
+    let order = contract.on_order_payment(0, Ok(()));
+    assert_eq!(order.status, OrderStatus::Paid);
+    assert_eq!(contract.get_patient_orders(0, None, None).len(), 1);
+    assert_eq!(contract.get_bought_medicine_by_patient(0).len(), 1);
+}
+
+#[test]
+fn failed_payment_restores_stock() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_medicine_to_contract(&mut contract);
+
+    set_context_with_deposit(accounts(3), 180);
+    contract.buy_medicine(0, 2, 0, None, None);
+
+    set_context(owner());
+    let order = contract.on_order_payment(0, Err(PromiseError::Failed));
+    assert_eq!(order.status, OrderStatus::Failed);
+    assert_eq!(contract.get_medicine_by_id(0).unwrap().quantity, 5);
+    assert!(contract.get_bought_medicine_by_patient(0).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Attached deposit must cover the total price of 180")]
+fn buy_medicine_requires_deposit() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_medicine_to_contract(&mut contract);
+
+    set_context_with_deposit(accounts(3), 179);
+    contract.buy_medicine(0, 2, 0, None, None);
+}
+
+fn add_pharmacy_to_contract(contract: &mut Contract, account_id: AccountId, city: &str) -> u32 {
+    register_storage(contract, account_id.clone());
+    set_context_with_deposit(account_id.clone(), FEE);
+    let id = contract.register_pharmacy(PharmacyInput {
+        name: "Corner Pharmacy".to_string(),
+        license_number: "PH123".to_string(),
+        email: "pharmacy@example.com".to_string(),
+        phone: "0700000001".to_string(),
+        city: city.to_string(),
+        address: format!("{} Main Street", city),
+        account_id,
+    });
+    set_context(owner());
+    contract.approve_pharmacy(id);
+    id
+}
+
+// Prices medicine 0 at 80 in the pharmacy and receives one batch of it
+fn stock_pharmacy(contract: &mut Contract, account_id: AccountId, pharmacy_id: u32, quantity: u128) {
+    set_context(account_id);
+    contract.set_pharmacy_price(0, 80, 0);
+    if quantity > 0 {
+        contract.add_batch(0, "LOT-1".to_string(), 0, 365 * 24 * HOUR, quantity, Some(pharmacy_id));
     }
- 
-     fn add_doctor_to_contract(contract: &mut Contract) {
-         contract.add_doctor(DoctorInput {
-            title: "Dr.".to_string(),
-            first_name: "John".to_string(),
-            last_name: "Doe".to_string(),
-            gender: "Male".to_string(),
-            designation: "Cardiologist".to_string(),
-            last_work: "Hospital A".to_string(),
-            email: "john.doe@example.com".to_string(),
-            college_name: "Medical College".to_string(),
-            college_id: "MC123".to_string(),
-            joining_year: 2010,
-            end_year: 2015,
-            specialization: "Heart Surgery".to_string(),
-            registration_id: "REG123".to_string(),
-            college_address: "Meru 567".to_string(),
-            account_id: "bob.near".parse().unwrap(),
-            profile_pic: "profile.jpg".to_string(),
-            bio: "Experienced doctor in cardiology.".to_string(),
-         });
- 
-         contract.add_doctor(DoctorInput {
-            title: "Dr.".to_string(),
-            first_name: "John".to_string(),
-            last_name: "Doe".to_string(),
-            gender: "Male".to_string(),
-            designation: "Cardiologist".to_string(),
-            last_work: "Hospital A".to_string(),
-            email: "john.doe@example.com".to_string(),
-            college_name: "Medical College".to_string(),
-            college_id: "MC123".to_string(),
-            joining_year: 2010,
-            end_year: 2015,
-            specialization: "Heart Surgery".to_string(),
-            registration_id: "REG123".to_string(),
-            college_address: "Meru 567".to_string(),
-            account_id: "bob.near".parse().unwrap(),
-            profile_pic: "profile.jpg".to_string(),
-            bio: "Experienced doctor in cardiology.".to_string(),
-         });
-     }
- 
-     #[test]
-     fn get_doctor_by_id() {
-         let mut contract = get_contract();
-         add_doctor_to_contract(&mut contract);
- 
-         let doctor = contract.get_doctor_id(1).unwrap();
- 
-         assert_eq!(doctor.id, 1);
-     }
- 
-
- }
-
- //implementation of the nft_transfer method. This transfers the NFT from the current owner to the receiver. 
-#[payable]
-fn nft_transfer(
-    &mut self,
-    receiver_id: AccountId,
-    token_id: TokenId,
-    memo: Option<String>,
-) {
-    //assert that the user attached exactly 1 yoctoNEAR. This is for security and so that the user will be redirected to the NEAR wallet. 
-    assert_one_yocto();
-    //get the sender to transfer the token from the sender to the receiver
-    let sender_id = env::predecessor_account_id();
-
-    //call the internal transfer method
-    self.internal_transfer(
-        &sender_id,
-        &receiver_id,
-        &token_id,
-        memo
+}
+
+#[test]
+fn pharmacies_with_stock_are_found_by_city() {
+    let mut contract = get_contract();
+    add_medicine_to_contract(&mut contract);
+    let stocked = add_pharmacy_to_contract(&mut contract, accounts(1), "Meru");
+    let sold_out = add_pharmacy_to_contract(&mut contract, accounts(2), "Meru");
+    let elsewhere = add_pharmacy_to_contract(&mut contract, accounts(4), "Nairobi");
+
+    stock_pharmacy(&mut contract, accounts(1), stocked, 10);
+    stock_pharmacy(&mut contract, accounts(2), sold_out, 0);
+    stock_pharmacy(&mut contract, accounts(4), elsewhere, 10);
+
+    let offers = contract.get_pharmacies_with_stock(0, "meru".to_string(), None, None);
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers[0].pharmacy.id, stocked);
+    assert_eq!(offers[0].stock.price, 80);
+    assert_eq!(contract.get_pharmacy_inventory(sold_out, None, None).len(), 1);
+    assert!(!contract.has_role(accounts(1), Role::Pharmacist));
+}
+
+#[test]
+fn buy_medicine_from_pharmacy_uses_its_stock_and_price() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_medicine_to_contract(&mut contract);
+    let pharmacy_id = add_pharmacy_to_contract(&mut contract, accounts(1), "Meru");
+    stock_pharmacy(&mut contract, accounts(1), pharmacy_id, 4);
+
+    set_context_with_deposit(accounts(3), 160);
+    contract.buy_medicine(0, 2, 0, None, Some(pharmacy_id));
+
+    let order = contract.get_order(0).unwrap();
+    assert_eq!((order.price, order.payment_amount, order.pharmacy_id), (80, 160, Some(pharmacy_id)));
+    assert_eq!(contract.get_medicine_by_id(0).unwrap().quantity, 5);
+    assert_eq!(contract.get_pharmacy_inventory(pharmacy_id, None, None)[0].quantity, 2);
+
+    set_context(owner());
+    contract.on_order_payment(0, Err(PromiseError::Failed));
+    assert_eq!(contract.get_pharmacy_inventory(pharmacy_id, None, None)[0].quantity, 4);
+}
+
+#[test]
+#[should_panic(expected = "Pharmacy is not approved")]
+fn unapproved_pharmacy_cannot_set_stock() {
+    let mut contract = get_contract();
+    add_medicine_to_contract(&mut contract);
+    register_storage(&mut contract, accounts(1));
+    set_context_with_deposit(accounts(1), FEE);
+    contract.register_pharmacy(PharmacyInput {
+        name: "Corner Pharmacy".to_string(),
+        license_number: "PH123".to_string(),
+        email: "pharmacy@example.com".to_string(),
+        phone: "0700000001".to_string(),
+        city: "Meru".to_string(),
+        address: "Meru Main Street".to_string(),
+        account_id: accounts(1),
+    });
+
+    set_context(accounts(1));
+    contract.set_pharmacy_price(0, 80, 0);
+}
+
+#[test]
+fn buy_medicine_dispenses_first_expiring_batches() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_medicine_to_contract(&mut contract);
+    register_storage(&mut contract, owner());
+    set_context(owner());
+    let early = contract.add_batch(0, "LOT-EARLY".to_string(), 0, 10 * 24 * HOUR, 2, None);
+
+    set_context_with_deposit(accounts(3), 270);
+    contract.buy_medicine(0, 3, 0, None, None);
+
+    assert_eq!(contract.get_order(0).unwrap().batches, vec![(early, 2), (0, 1)]);
+    assert_eq!(contract.get_batch(0).unwrap().remaining, 4);
+    assert_eq!(contract.get_medicine_by_id(0).unwrap().quantity, 4);
+}
+
+#[test]
+#[should_panic(expected = "Not enough unexpired stock")]
+fn expired_batches_are_reported_and_not_sold() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_medicine_to_contract(&mut contract);
+    register_storage(&mut contract, owner());
+    set_context(owner());
+    let early = contract.add_batch(0, "LOT-EARLY".to_string(), 0, 10 * 24 * HOUR, 2, None);
+
+    set_context_at(owner(), 11 * 24 * HOUR);
+    let expiring: Vec<u32> = contract.get_expiring_batches(Some(30), None, None).iter().map(|b| b.id).collect();
+    assert_eq!(expiring, vec![early]);
+    assert!(contract.get_batch(early).unwrap().is_expired(env::block_timestamp()));
+
+    let mut context = VMContextBuilder::new();
+    context.current_account_id(owner());
+    context.predecessor_account_id(accounts(3));
+    context.block_timestamp(11 * 24 * HOUR);
+    context.attached_deposit(NearToken::from_yoctonear(1_000));
+    testing_env!(context.build());
+    contract.buy_medicine(0, 6, 0, None, None);
+}
+
+#[test]
+fn lot_recall_notifies_buyers_and_prescribers() {
+    let mut contract = get_contract();
+    let id = prescribe_in_contract(&mut contract);
+    add_patient_to_contract(&mut contract, accounts(4));
+    set_context_with_deposit(accounts(3), 180);
+    contract.buy_medicine(0, 2, 0, Some(id), None);
+
+    register_storage(&mut contract, owner());
+    set_context(owner());
+    contract.update_medicine_schedule(0, false, None);
+    let lot = contract.add_batch(0, "LOT-2".to_string(), 0, 10 * 24 * HOUR, 2, None);
+    set_context_with_deposit(accounts(4), 90);
+    contract.buy_medicine(0, 1, 1, None, None);
+    assert_eq!(contract.get_order(1).unwrap().batches, vec![(lot, 1)]);
+
+    set_context(owner());
+    let recall_id = contract.recall_lot(0, "OPENING".to_string(), "Contamination".to_string());
+
+    let notified: Vec<AccountId> = contract
+        .notifications
+        .iter()
+        .filter(|notification| notification.message == "Paracetamol lot OPENING has been recalled: Contamination")
+        .map(|notification| notification.account_id.clone())
+        .collect();
+    assert_eq!(notified, vec![accounts(2), accounts(3)]);
+    assert_eq!(contract.get_batch(0).unwrap().recall_id, Some(recall_id));
+    assert!(contract.get_batch(lot).unwrap().recall_id.is_none());
+    assert_eq!(contract.get_medicine_recalls(0, None, None).len(), 1);
+}
+
+#[test]
+#[should_panic(expected = "Medicine has been recalled")]
+fn manufacturer_recall_blocks_sales() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    add_medicine_to_contract(&mut contract);
+    contract.set_medicine_manufacturer(0, accounts(5));
+
+    set_context(accounts(5));
+    contract.recall_medicine(0, "Mislabelled strength".to_string());
+
+    set_context_with_deposit(accounts(3), 90);
+    contract.buy_medicine(0, 1, 0, None, None);
+}
+
+#[test]
+fn recalled_medicine_has_no_offers() {
+    let mut contract = get_contract();
+    add_medicine_to_contract(&mut contract);
+    let pharmacy_id = add_pharmacy_to_contract(&mut contract, accounts(1), "Meru");
+    stock_pharmacy(&mut contract, accounts(1), pharmacy_id, 10);
+
+    set_context(owner());
+    contract.recall_medicine(0, "Mislabelled strength".to_string());
+    assert!(contract.get_pharmacies_with_stock(0, "Meru".to_string(), None, None).is_empty());
+    assert_eq!(contract.sellable_quantity(StockHolder::Pharmacy(pharmacy_id), 0), 0);
+    assert_eq!(contract.sellable_quantity(StockHolder::Catalog, 0), 0);
+}
+
+#[test]
+fn naming_a_manufacturer_is_audited() {
+    let mut contract = get_contract();
+    add_medicine_to_contract(&mut contract);
+    contract.set_medicine_manufacturer(0, accounts(5));
+
+    assert!(contract.has_role(accounts(5), Role::Manufacturer));
+    let entries = contract.get_actor_audit_log(owner(), None, None);
+    assert_eq!(entries.last().unwrap().action, AuditAction::RoleGranted);
+}
+
+#[test]
+#[should_panic(expected = "Only an admin or the medicine's manufacturer can recall it")]
+fn only_admin_or_manufacturer_can_recall() {
+    let mut contract = get_contract();
+    add_medicine_to_contract(&mut contract);
+    contract.grant_role(accounts(5), Role::Manufacturer);
+
+    set_context(accounts(5));
+    contract.recall_medicine(0, "Mislabelled strength".to_string());
+}
+
+// Medicine 0 made by accounts(5), which registers batch 1 of it
+fn register_manufactured_batch(contract: &mut Contract) -> u32 {
+    add_medicine_to_contract(contract);
+    contract.set_medicine_manufacturer(0, accounts(5));
+    register_storage(contract, accounts(5));
+    set_context(accounts(5));
+    contract.register_batch(0, "LOT-M".to_string(), 0, 365 * 24 * HOUR, 10, "Factory".to_string())
+}
+
+#[test]
+fn batch_custody_chain_ends_in_pharmacy_stock() {
+    let mut contract = get_contract();
+    let pharmacy_id = add_pharmacy_to_contract(&mut contract, accounts(1), "Meru");
+    let batch_id = register_manufactured_batch(&mut contract);
+    stock_pharmacy(&mut contract, accounts(1), pharmacy_id, 0);
+    register_storage(&mut contract, accounts(4));
+    assert_eq!(contract.get_batch(batch_id).unwrap().holder, StockHolder::SupplyChain);
+
+    set_context(accounts(5));
+    contract.transfer_batch(batch_id, accounts(4), "Warehouse".to_string());
+    set_context(accounts(4));
+    contract.accept_batch(batch_id);
+    contract.transfer_batch(batch_id, accounts(1), "Meru Main Street".to_string());
+    set_context(accounts(1));
+    contract.accept_batch(batch_id);
+
+    let chain: Vec<(AccountId, Option<AccountId>)> = contract
+        .get_provenance(batch_id)
+        .into_iter()
+        .map(|record| (record.custodian, record.from))
+        .collect();
+    assert_eq!(
+        chain,
+        vec![
+            (accounts(5), None),
+            (accounts(4), Some(accounts(5))),
+            (accounts(1), Some(accounts(4))),
+        ]
+    );
+    assert_eq!(contract.get_batch(batch_id).unwrap().holder, StockHolder::Pharmacy(pharmacy_id));
+    assert_eq!(contract.get_pharmacy_inventory(pharmacy_id, None, None)[0].quantity, 10);
+}
+
+#[test]
+#[should_panic(expected = "Batch is not being handed to the caller")]
+fn only_the_receiver_accepts_a_batch() {
+    let mut contract = get_contract();
+    let batch_id = register_manufactured_batch(&mut contract);
+    contract.transfer_batch(batch_id, accounts(4), "Warehouse".to_string());
+
+    set_context(accounts(3));
+    contract.accept_batch(batch_id);
+}
+
+fn hashes(serials: &[&str]) -> Vec<String> {
+    serials.iter().map(|serial| serial.to_string()).collect()
+}
+
+#[test]
+fn sold_packs_stay_genuine_until_the_seller_dispenses_them() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    let batch_id = register_manufactured_batch(&mut contract);
+    contract.register_serials(batch_id, hashes(&["h1", "h2", "h3"]));
+    assert_eq!(contract.verify_serial("h1".to_string()), SerialStatus::Genuine);
+    assert_eq!(contract.verify_serial("forged".to_string()), SerialStatus::Unknown);
+
+    contract.transfer_batch(batch_id, owner(), "Central store".to_string());
+    register_storage(&mut contract, owner());
+    set_context(owner());
+    contract.accept_batch(batch_id);
+
+    set_context_with_deposit(accounts(3), 180);
+    contract.buy_medicine(0, 2, 0, None, None);
+    assert!(contract.get_order(0).unwrap().serials.is_empty());
+    assert_eq!(contract.verify_serial("h1".to_string()), SerialStatus::Genuine);
+
+    // The packs actually handed over are the ones marked
+    set_context(owner());
+    contract.dispense_serials(0, hashes(&["h3", "h1"]));
+    assert_eq!(contract.get_order(0).unwrap().serials, hashes(&["h3", "h1"]));
+    assert_eq!(contract.verify_serial("h3".to_string()), SerialStatus::AlreadyDispensed);
+    assert_eq!(contract.verify_serial("h2".to_string()), SerialStatus::Genuine);
+
+    contract.on_order_payment(0, Err(PromiseError::Failed));
+    assert_eq!(contract.verify_serial("h3".to_string()), SerialStatus::Genuine);
+
+    contract.recall_lot(0, "LOT-M".to_string(), "Contamination".to_string());
+    assert_eq!(contract.verify_serial("h3".to_string()), SerialStatus::Recalled);
+}
+
+#[test]
+fn dispensing_serials_bills_the_bytes_written() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    let batch_id = register_manufactured_batch(&mut contract);
+    let serials: Vec<String> = ["a", "b", "c"].iter().map(|prefix| prefix.repeat(500)).collect();
+    contract.register_serials(batch_id, serials.clone());
+    contract.transfer_batch(batch_id, owner(), "Central store".to_string());
+    register_storage(&mut contract, owner());
+    set_context(owner());
+    contract.accept_batch(batch_id);
+    set_context_with_deposit(accounts(3), 270);
+    contract.buy_medicine(0, 3, 0, None, None);
+
+    set_context(owner());
+    contract.flush_collections();
+    let (usage, available) = (env::storage_usage(), contract.storage_balance_of(owner()).unwrap().available.0);
+    contract.dispense_serials(0, serials);
+    contract.flush_collections();
+    let written = (env::storage_usage() - usage) as u128;
+    assert!(written > 1_500);
+    assert_eq!(
+        available - contract.storage_balance_of(owner()).unwrap().available.0,
+        written * env::storage_byte_cost().as_yoctonear()
     );
-}
\ No newline at end of file
+}
+
+#[test]
+#[should_panic(expected = "Serial h9 is not from a batch this order was filled from")]
+fn dispensed_serials_must_come_from_the_order_batches() {
+    let mut contract = get_contract();
+    add_patient_to_contract(&mut contract, accounts(3));
+    let batch_id = register_manufactured_batch(&mut contract);
+    contract.register_serials(batch_id, hashes(&["h9"]));
+
+    // Bought from the opening batch, which the manufactured batch is not
+    set_context_with_deposit(accounts(3), 90);
+    contract.buy_medicine(0, 1, 0, None, None);
+
+    register_storage(&mut contract, owner());
+    set_context(owner());
+    contract.dispense_serials(0, hashes(&["h9"]));
+}
+
+#[test]
+#[should_panic(expected = "Serial h1 is already registered")]
+fn serials_are_registered_once() {
+    let mut contract = get_contract();
+    let batch_id = register_manufactured_batch(&mut contract);
+    contract.register_serials(batch_id, hashes(&["h1"]));
+    contract.register_serials(batch_id, hashes(&["h1"]));
+}
+
+#[test]
+#[should_panic(expected = "Only accounts with one of the roles [Admin] can call this method")]
+fn pharmacies_cannot_relax_medicine_schedules() {
+    let mut contract = get_contract();
+    add_medicine_to_contract(&mut contract);
+    contract.update_medicine_schedule(0, true, Some(2));
+    add_pharmacy_to_contract(&mut contract, accounts(1), "Meru");
+
+    set_context(accounts(1));
+    contract.update_medicine_schedule(0, false, None);
+}
+
+fn legacy_patient(account_id: AccountId, medical_history: Vec<&str>) -> migrate::LegacyPatient {
+    let input = patient_input(account_id);
+    migrate::LegacyPatient {
+        id: 0,
+        title: input.title,
+        first_name: input.first_name,
+        last_name: input.last_name,
+        gender: input.gender,
+        condition: input.condition,
+        phone: input.phone,
+        email: input.email,
+        dob: input.dob,
+        city: input.city,
+        address: input.address,
+        doctor: input.doctor,
+        profile_pic: input.profile_pic,
+        account_id: input.account_id,
+        message: input.message,
+        medical_history: medical_history.into_iter().map(String::from).collect(),
+        bought_medicine: vec![],
+    }
+}
+
+#[test]
+fn migrate_separates_shared_prefix_records() {
+    set_context(owner());
+    {
+        let mut patients = Vector::new(migrate::LEGACY_SHARED_PREFIX);
+        let mut prescriptions = Vector::new(migrate::LEGACY_SHARED_PREFIX);
+        // Slot 0: patient, overwritten copy history in slots 1 and 2.
+        patients.push(legacy_patient(accounts(3), vec![]));
+        patients.push(legacy_patient(accounts(3), vec!["Malaria"]));
+        patients.push(legacy_patient(accounts(3), vec!["Recovered"]));
+        patients.push(legacy_patient(accounts(4), vec![]));
+        patients.flush();
+        // A prescription pushed after the patients lands on slot 0 and wins it.
+        prescriptions.push(migrate::LegacyPrescription {
+            id: 7,
+            medicine_id: 1,
+            patient_id: 0,
+            doctor_id: 0,
+            date: 0,
+        });
+        prescriptions.flush();
+
+        let mut appointments = Vector::new(b"e");
+        appointments.push(migrate::LegacyAppointment {
+            id: 3,
+            patient_id: 0,
+            doctor_id: 0,
+            from: "09:00".to_string(),
+            to: "09:30".to_string(),
+            appointment_date: "2024-10-01".to_string(),
+            condition: "Fever".to_string(),
+            status: migrate::LegacyAppointmentStatus::Pending,
+            message: "Check up".to_string(),
+            is_open: true,
+        });
+        appointments.flush();
+
+        let mut doctors = Vector::new(b"d");
+        let input = doctor_input(accounts(1));
+        let legacy_doctor = |is_approved| migrate::LegacyDoctor {
+            id: 0,
+            title: input.title.clone(),
+            first_name: input.first_name.clone(),
+            last_name: input.last_name.clone(),
+            gender: input.gender.clone(),
+            designation: input.designation.clone(),
+            last_work: input.last_work.clone(),
+            email: input.email.clone(),
+            college_name: input.college_name.clone(),
+            college_id: input.college_id.clone(),
+            joining_year: input.joining_year.clone(),
+            end_year: input.end_year.clone(),
+            specialization: input.specialization.clone(),
+            registration_id: input.registration_id.clone(),
+            college_address: input.college_address.clone(),
+            account_id: input.account_id.clone(),
+            profile_pic: input.profile_pic.clone(),
+            bio: input.bio.clone(),
+            appointment_counts: 0,
+            successful_treaments: 0,
+            is_approved,
+        };
+        doctors.push(legacy_doctor(false));
+        doctors.push(legacy_doctor(true));
+        doctors.flush();
+
+        let mut drugs = Vector::new(b"h");
+        drugs.push(migrate::LegacyMedicine {
+            id: 0,
+            doctor_id: 0,
+            name: "Paracetamol".to_string(),
+            brand: "Panadol".to_string(),
+            manufacturer: "GSK".to_string(),
+            manufacturing_date: "2024-01-01".to_string(),
+            expiry_date: "2026-01-01".to_string(),
+            company_email: "gsk@example.com".to_string(),
+            discount: 150,
+            manufacturer_address: "London".to_string(),
+            price: 100,
+            quantity: 0,
+            current_location: "Nairobi".to_string(),
+            phone_no: 0,
+            image: String::new(),
+            description: String::new(),
+            availability: false,
+        });
+        drugs.flush();
+
+        let old = migrate::OldContract {
+            owner: owner(),
+            users: IterableSet::new(b"s"),
+            patients,
+            no_of_patients: 0,
+            doctors,
+            no_of_doctors: 1,
+            drugs,
+            no_of_drugs: 1,
+            prescriptions,
+            no_of_prescriptions: 1,
+            appointments,
+            no_of_appointments: 1,
+            notifications: Vector::new(b"k"),
+            no_of_notifications: 0,
+            orders: Vector::new(b"o"),
+            messages: Vector::new(b"m"),
+            appointment_fee: 1,
+            registration_fee: 2,
+        };
+        env::state_write(&old);
+    }
+
+    let mut contract = Contract::migrate();
+
+    let patients = contract.get_all_registered_patients(None, None);
+    assert_eq!(patients.len(), 2);
+    set_context(accounts(3));
+    assert_eq!(history_texts(&mut contract, 0), vec!["Malaria", "Recovered"]);
+    assert_eq!(contract.get_patient_id(1).unwrap().account_id, accounts(4));
+    assert_eq!(contract.get_patient_by_account(accounts(4)).unwrap().id, 1);
+
+    let prescriptions = contract.get_all_prescription_details(None, None);
+    assert_eq!(prescriptions.len(), 1);
+    assert_eq!(prescriptions[0].id, 7);
+    // Legacy records named patient 0 whoever they were for
+    assert_eq!(prescriptions[0].patient_id, migrate::UNATTRIBUTED_PATIENT);
+    assert!(contract.get_patient_prescriptions(0, None, None).is_empty());
+
+    assert_eq!(contract.get_all_doctors_data(None, None).len(), 1);
+    assert!(contract.get_doctor_details(0).unwrap().is_approved);
+    assert_eq!(contract.get_doctor_by_account(accounts(1)).unwrap().id, 0);
+    assert_eq!(contract.registration_fee, 2);
+    let appointment = &contract.get_all_appointments(None, None)[0];
+    assert_eq!(appointment.slot, slot(19_997, 9 * 60));
+    assert_eq!(appointment.status, AppointmentStatus::Requested);
+    assert_eq!(appointment.patient_id, migrate::UNATTRIBUTED_PATIENT);
+    assert_eq!(contract.get_medicine_by_id(0).unwrap().discount, 100);
+}
@@ -1,12 +1,42 @@
-use crate::*;
-
-pub fn account_vec_to_set(
-    account_vec: Vec<AccountId>,
-    storage_key: &'static [u8],
-) -> IterableSet<AccountId> {
-    let mut set = IterableSet::new(storage_key);
-    for element in account_vec.iter() {
-        set.insert(element.clone());
+// Shared `from_index`/`limit` paging for list views. Skipping happens before
+// cloning so only the requested page is copied; a missing `limit` returns
+// everything from `from_index` on.
+pub fn paginate<'a, T, I>(iter: I, from_index: Option<u32>, limit: Option<u32>) -> Vec<T>
+where
+    T: Clone + 'a,
+    I: Iterator<Item = &'a T>,
+{
+    iter.skip(from_index.unwrap_or(0) as usize)
+        .take(limit.map_or(usize::MAX, |limit| limit as usize))
+        .cloned()
+        .collect()
+}
+
+pub const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Days since 1970-01-01 of a "YYYY-MM-DD" date as entered in the frontend
+// forms. Dates before the epoch clamp to it.
+pub fn parse_date(date: &str) -> Option<u32> {
+    let parts: Option<Vec<i64>> = date.trim().split('-').map(|part| part.parse().ok()).collect();
+    match parts.as_deref() {
+        Some(&[year, month, day]) if (1..=12).contains(&month) && (1..=31).contains(&day) => {
+            Some(days_from_civil(year, month, day).clamp(0, u32::MAX as i64) as u32)
+        }
+        _ => None,
     }
-    set
-}
\ No newline at end of file
+}
+
+// Block timestamp at midnight UTC of a "YYYY-MM-DD" date
+pub fn date_timestamp(date: &str) -> Option<u64> {
+    parse_date(date).map(|days| days as u64 * NANOS_PER_DAY)
+}